@@ -0,0 +1,138 @@
+use crate::chip8::Chip8;
+
+//Register numbers used in the access masks; V0-VF are 0x0-0xf
+pub const REG_I: u8 = 16;
+pub const REG_DT: u8 = 17;
+pub const REG_ST: u8 = 18;
+
+//Memory and registers the instruction at pc is going to access, worked out from the machine state
+//before it is executed. Memory ranges are (start, length).
+#[derive(Debug, Default)]
+pub struct Access {
+    pub mem_reads: Option<(u16, u16)>,
+    pub mem_writes: Option<(u16, u16)>,
+    pub reg_reads: u32,
+    pub reg_writes: u32,
+}
+
+impl Access {
+    pub fn reads_reg(&self, reg: u8) -> bool {
+        self.reg_reads & (1 << reg) != 0
+    }
+
+    pub fn writes_reg(&self, reg: u8) -> bool {
+        self.reg_writes & (1 << reg) != 0
+    }
+}
+
+fn bit(reg: u16) -> u32 {
+    1 << reg
+}
+
+//Mask of the registers V0 to Vx
+fn range(x: u16) -> u32 {
+    (1 << (x + 1)) - 1
+}
+
+pub fn next_access(chip: &Chip8) -> Access {
+    let opcode = crate::disasm::fetch(&chip.mem, chip.pc as usize);
+    let x = (opcode & 0xf00) >> 8;
+    let y = (opcode & 0xf0) >> 4;
+    let n = opcode & 0xf;
+    let i = chip.i;
+    let vf = bit(0xf);
+
+    let mut access = Access::default();
+    match (opcode >> 12, n, opcode & 0xff) {
+        (0x3, _, _) | (0x4, _, _) | (0xe, _, 0x9e) | (0xe, _, 0xa1) => access.reg_reads = bit(x),
+        (0x5, 0, _) | (0x9, 0, _) => access.reg_reads = bit(x) | bit(y),
+        (0x6, _, _) | (0xc, _, _) => access.reg_writes = bit(x),
+        (0x7, _, _) => {
+            access.reg_reads = bit(x);
+            access.reg_writes = bit(x);
+        }
+        (0x8, 0x0, _) => {
+            access.reg_reads = bit(y);
+            access.reg_writes = bit(x);
+        }
+        (0x8, 0x1..=0x3, _) => {
+            access.reg_reads = bit(x) | bit(y);
+            access.reg_writes = bit(x) | if chip.quirks.vf_reset { vf } else { 0 };
+        }
+        (0x8, 0x4 | 0x5 | 0x7, _) => {
+            access.reg_reads = bit(x) | bit(y);
+            access.reg_writes = bit(x) | vf;
+        }
+        (0x8, 0x6 | 0xe, _) => {
+            access.reg_reads = if chip.quirks.shift_vy { bit(y) } else { bit(x) };
+            access.reg_writes = bit(x) | vf;
+        }
+        (0xa, _, _) => access.reg_writes = bit(REG_I as u16),
+        (0xb, _, _) => access.reg_reads = if chip.quirks.jump_vx { bit(x) } else { bit(0) },
+        (0xd, _, _) => {
+            access.reg_reads = bit(x) | bit(y) | bit(REG_I as u16);
+            access.reg_writes = vf;
+            access.mem_reads = Some((i, n));
+        }
+        (0xf, _, 0x07) => {
+            access.reg_reads = bit(REG_DT as u16);
+            access.reg_writes = bit(x);
+        }
+        (0xf, _, 0x0a) => access.reg_writes = bit(x),
+        (0xf, _, 0x15) => {
+            access.reg_reads = bit(x);
+            access.reg_writes = bit(REG_DT as u16);
+        }
+        (0xf, _, 0x18) => {
+            access.reg_reads = bit(x);
+            access.reg_writes = bit(REG_ST as u16);
+        }
+        (0xf, _, 0x1e) => {
+            access.reg_reads = bit(x) | bit(REG_I as u16);
+            access.reg_writes = bit(REG_I as u16);
+        }
+        (0xf, _, 0x29) => {
+            access.reg_reads = bit(x);
+            access.reg_writes = bit(REG_I as u16);
+        }
+        (0xf, _, 0x33) => {
+            access.reg_reads = bit(x) | bit(REG_I as u16);
+            access.mem_writes = Some((i, 3));
+        }
+        (0xf, _, 0x55) => {
+            access.reg_reads = range(x) | bit(REG_I as u16);
+            access.mem_writes = Some((i, x + 1));
+        }
+        (0xf, _, 0x65) => {
+            access.reg_reads = bit(REG_I as u16);
+            access.reg_writes = range(x);
+            access.mem_reads = Some((i, x + 1));
+        }
+        _ => {}
+    }
+    if chip.quirks.load_store_inc_i && opcode >> 12 == 0xf && matches!(opcode & 0xff, 0x55 | 0x65) {
+        access.reg_writes |= bit(REG_I as u16);
+    }
+    access
+}
+
+//Parses V0-VF, I, DT and ST into a register number
+pub fn parse_register(name: &str) -> Option<u8> {
+    let upper = name.to_uppercase();
+    match upper.as_str() {
+        "I" => Some(REG_I),
+        "DT" => Some(REG_DT),
+        "ST" => Some(REG_ST),
+        _ if upper.len() == 2 && upper.starts_with('V') => u8::from_str_radix(&upper[1..], 16).ok(),
+        _ => None,
+    }
+}
+
+pub fn register_name(reg: u8) -> String {
+    match reg {
+        REG_I => "I".to_string(),
+        REG_DT => "DT".to_string(),
+        REG_ST => "ST".to_string(),
+        _ => format!("V{:X}", reg),
+    }
+}
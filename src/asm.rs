@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::fmt;
+
+//Two pass assembler for the mnemonics of Cowgod's Chip-8 technical reference, see disasm.rs.
+//Besides the instructions it knows labels ("loop:"), ';' comments and the DB/DW data directives.
+
+const START_ADDR: u16 = 0x200;
+
+#[derive(Debug)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+pub struct Program {
+    pub bytes: Vec<u8>,
+    pub labels: HashMap<String, u16>,
+    pub lines: Vec<(usize, u16)>,
+    //source line and address of every statement
+}
+
+enum Operand {
+    V(u16),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    B,
+    Value(u16),
+}
+
+struct Statement<'a> {
+    line: usize,
+    mnemonic: String,
+    operands: Vec<&'a str>,
+}
+
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    //First pass: split the source into statements and assign an address to every label
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut lines = Vec::new();
+    let mut addr = START_ADDR;
+    for (n, raw) in source.lines().enumerate() {
+        let line = n + 1;
+        let mut text = raw.split(';').next().unwrap_or("").trim();
+        while let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if label.is_empty() || !label.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.') {
+                break;
+            }
+            if labels.insert(label.to_string(), addr).is_some() {
+                return Err(AsmError { line, message: format!("duplicate label {}", label) });
+            }
+            text = text[colon + 1..].trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, rest) = match text.find(char::is_whitespace) {
+            Some(space) => (&text[..space], text[space..].trim()),
+            None => (text, ""),
+        };
+        let operands: Vec<&str> = if rest.is_empty() { Vec::new() } else { rest.split(',').map(|o| o.trim()).collect() };
+        let mnemonic = mnemonic.to_uppercase();
+        lines.push((line, addr));
+        addr += match mnemonic.as_str() {
+            "DB" => operands.len() as u16,
+            "DW" => 2 * operands.len() as u16,
+            _ => 2,
+        };
+        statements.push(Statement { line, mnemonic, operands });
+    }
+
+    //Second pass: encode the statements now that every label is known
+    let mut bytes = Vec::new();
+    for statement in &statements {
+        let operands = statement.operands.iter()
+            .map(|o| parse_operand(o, &labels))
+            .collect::<Result<Vec<Operand>, String>>()
+            .map_err(|message| AsmError { line: statement.line, message })?;
+
+        match statement.mnemonic.as_str() {
+            "DB" => {
+                for operand in operands {
+                    bytes.push(value(&operand, 0xff).map_err(|message| AsmError { line: statement.line, message })? as u8);
+                }
+            }
+            "DW" => {
+                for operand in operands {
+                    let word = value(&operand, 0xffff).map_err(|message| AsmError { line: statement.line, message })?;
+                    bytes.extend_from_slice(&word.to_be_bytes());
+                }
+            }
+            _ => {
+                let opcode = encode(&statement.mnemonic, &operands)
+                    .map_err(|message| AsmError { line: statement.line, message })?;
+                bytes.extend_from_slice(&opcode.to_be_bytes());
+            }
+        }
+    }
+
+    Ok(Program { bytes, labels, lines })
+}
+
+fn parse_operand(text: &str, labels: &HashMap<String, u16>) -> Result<Operand, String> {
+    let upper = text.to_uppercase();
+    match upper.as_str() {
+        "I" => return Ok(Operand::I),
+        "[I]" => return Ok(Operand::IndirectI),
+        "DT" => return Ok(Operand::Dt),
+        "ST" => return Ok(Operand::St),
+        "K" => return Ok(Operand::K),
+        "F" => return Ok(Operand::F),
+        "B" => return Ok(Operand::B),
+        _ => {}
+    }
+    if upper.len() == 2 && upper.starts_with('V') {
+        if let Ok(reg) = u16::from_str_radix(&upper[1..], 16) {
+            return Ok(Operand::V(reg));
+        }
+    }
+    if let Some(number) = parse_number(text) {
+        return Ok(Operand::Value(number));
+    }
+    labels.get(text).map(|addr| Operand::Value(*addr)).ok_or(format!("unknown operand {}", text))
+}
+
+pub fn parse_number(text: &str) -> Option<u16> {
+    let lower = text.to_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x").or(lower.strip_prefix('#')).or(lower.strip_prefix('$')) {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        u16::from_str_radix(bin, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+fn value(operand: &Operand, max: u16) -> Result<u16, String> {
+    match operand {
+        Operand::Value(v) if *v <= max => Ok(*v),
+        Operand::Value(v) => Err(format!("{:#x} does not fit into {:#x}", v, max)),
+        _ => Err("expected a number or label".to_string()),
+    }
+}
+
+fn encode(mnemonic: &str, operands: &[Operand]) -> Result<u16, String> {
+    use Operand::*;
+
+    let opcode = match (mnemonic, operands) {
+        ("CLS", []) => 0x00e0,
+        ("RET", []) => 0x00ee,
+        ("SYS", [addr]) => value(addr, 0xfff)?,
+        ("JP", [V(0), addr]) => 0xb000 | value(addr, 0xfff)?,
+        ("JP", [addr]) => 0x1000 | value(addr, 0xfff)?,
+        ("CALL", [addr]) => 0x2000 | value(addr, 0xfff)?,
+        ("SE", [V(x), V(y)]) => 0x5000 | x << 8 | y << 4,
+        ("SE", [V(x), kk]) => 0x3000 | x << 8 | value(kk, 0xff)?,
+        ("SNE", [V(x), V(y)]) => 0x9000 | x << 8 | y << 4,
+        ("SNE", [V(x), kk]) => 0x4000 | x << 8 | value(kk, 0xff)?,
+        ("LD", [V(x), V(y)]) => 0x8000 | x << 8 | y << 4,
+        ("LD", [V(x), Dt]) => 0xf007 | x << 8,
+        ("LD", [V(x), K]) => 0xf00a | x << 8,
+        ("LD", [V(x), IndirectI]) => 0xf065 | x << 8,
+        ("LD", [V(x), kk]) => 0x6000 | x << 8 | value(kk, 0xff)?,
+        ("LD", [I, addr]) => 0xa000 | value(addr, 0xfff)?,
+        ("LD", [Dt, V(x)]) => 0xf015 | x << 8,
+        ("LD", [St, V(x)]) => 0xf018 | x << 8,
+        ("LD", [F, V(x)]) => 0xf029 | x << 8,
+        ("LD", [B, V(x)]) => 0xf033 | x << 8,
+        ("LD", [IndirectI, V(x)]) => 0xf055 | x << 8,
+        ("ADD", [I, V(x)]) => 0xf01e | x << 8,
+        ("ADD", [V(x), V(y)]) => 0x8004 | x << 8 | y << 4,
+        ("ADD", [V(x), kk]) => 0x7000 | x << 8 | value(kk, 0xff)?,
+        ("OR", [V(x), V(y)]) => 0x8001 | x << 8 | y << 4,
+        ("AND", [V(x), V(y)]) => 0x8002 | x << 8 | y << 4,
+        ("XOR", [V(x), V(y)]) => 0x8003 | x << 8 | y << 4,
+        ("SUB", [V(x), V(y)]) => 0x8005 | x << 8 | y << 4,
+        ("SHR", [V(x)]) => 0x8006 | x << 8 | x << 4,
+        ("SHR", [V(x), V(y)]) => 0x8006 | x << 8 | y << 4,
+        ("SUBN", [V(x), V(y)]) => 0x8007 | x << 8 | y << 4,
+        ("SHL", [V(x)]) => 0x800e | x << 8 | x << 4,
+        ("SHL", [V(x), V(y)]) => 0x800e | x << 8 | y << 4,
+        ("RND", [V(x), kk]) => 0xc000 | x << 8 | value(kk, 0xff)?,
+        ("DRW", [V(x), V(y), n]) => 0xd000 | x << 8 | y << 4 | value(n, 0xf)?,
+        ("SKP", [V(x)]) => 0xe09e | x << 8,
+        ("SKNP", [V(x)]) => 0xe0a1 | x << 8,
+        _ => return Err(format!("invalid instruction {} with {} operand(s)", mnemonic, operands.len())),
+    };
+    Ok(opcode)
+}
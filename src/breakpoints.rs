@@ -0,0 +1,221 @@
+use std::fmt;
+
+use crate::access::{self, Access};
+use crate::asm::parse_number;
+use crate::chip8::Chip8;
+use crate::disasm;
+use crate::expr::{self, Expr};
+use crate::symbols::Symbols;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Watch {
+    Read,
+    Write,
+    Access,
+}
+
+#[derive(Clone, Debug)]
+pub enum Target {
+    Addr(u16),
+    //Opcode pattern like DXYN, X Y N and K match any digit
+    Opcode { pattern: String, mask: u16, value: u16 },
+    //Only the condition decides
+    Always,
+    Mem(Watch, u16, u16),
+    Reg(Watch, u8),
+}
+
+pub struct Breakpoint {
+    pub id: u32,
+    pub target: Target,
+    pub condition: Option<(String, Expr)>,
+    pub log: Option<String>,
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.log.is_some() { "log" } else { "break" };
+        match &self.target {
+            Target::Addr(addr) => write!(f, "{} at {:#05x}", kind, addr)?,
+            Target::Opcode { pattern, .. } => write!(f, "{} on {}", kind, pattern)?,
+            Target::Always => write!(f, "{}", kind)?,
+            Target::Mem(watch, start, len) => write!(f, "{} on {:?} of {:#05x}..{:#05x}", kind, watch, start, *start as u32 + *len as u32)?,
+            Target::Reg(watch, reg) => write!(f, "{} on {:?} of {}", kind, watch, access::register_name(*reg))?,
+        }
+        if let Some((text, _)) = &self.condition {
+            write!(f, " if {}", text)?;
+        }
+        if let Some(log) = &self.log {
+            write!(f, " \"{}\"", log)?;
+        }
+        Ok(())
+    }
+}
+
+//In u32 as a range can run past the end of the address space
+fn overlaps(range: Option<(u16, u16)>, start: u16, len: u16) -> bool {
+    match range {
+        Some((a, n)) => (a as u32) < start as u32 + len as u32 && (start as u32) < a as u32 + n as u32,
+        None => false,
+    }
+}
+
+impl Target {
+    fn matches(&self, chip: &Chip8, access: &Access) -> bool {
+        let reads = |w: &Watch| *w != Watch::Write;
+        let writes = |w: &Watch| *w != Watch::Read;
+        match self {
+            Target::Addr(addr) => chip.pc == *addr,
+            Target::Opcode { mask, value, .. } => disasm::fetch(&chip.mem, chip.pc as usize) & mask == *value,
+            Target::Always => true,
+            Target::Mem(watch, start, len) => {
+                (reads(watch) && overlaps(access.mem_reads, *start, *len))
+                    || (writes(watch) && overlaps(access.mem_writes, *start, *len))
+            }
+            Target::Reg(watch, reg) => {
+                (reads(watch) && access.reads_reg(*reg)) || (writes(watch) && access.writes_reg(*reg))
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Breakpoints {
+    list: Vec<Breakpoint>,
+    next_id: u32,
+}
+
+impl Breakpoints {
+    pub fn add(&mut self, target: Target, condition: Option<(String, Expr)>, log: Option<String>) -> &Breakpoint {
+        self.next_id += 1;
+        self.list.push(Breakpoint { id: self.next_id, target, condition, log });
+        self.list.last().unwrap()
+    }
+
+    pub fn remove(&mut self, id: u32) -> bool {
+        let len = self.list.len();
+        self.list.retain(|b| b.id != id);
+        self.list.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.list.iter()
+    }
+
+    //Checked before the instruction at pc executes. The messages of the logpoints go to log,
+    //the first breakpoint that fires is returned.
+    pub fn check<F: FnMut(String)>(&self, chip: &Chip8, mut log: F) -> Option<&Breakpoint> {
+        if self.list.is_empty() {
+            return None;
+        }
+        let access = access::next_access(chip);
+        let mut hit = None;
+        for b in &self.list {
+            if !b.target.matches(chip, &access) {
+                continue;
+            }
+            if let Some((_, condition)) = &b.condition {
+                if !condition.is_true(chip) {
+                    continue;
+                }
+            }
+            match &b.log {
+                Some(text) => match expr::format(text, chip) {
+                    Ok(message) => log(message),
+                    Err(e) => log(format!("Logpoint {}: {}", b.id, e)),
+                },
+                None => {
+                    hit = hit.or(Some(b));
+                }
+            }
+        }
+        hit
+    }
+}
+
+fn number(text: &str) -> Result<u16, String> {
+    parse_number(text).ok_or(format!("Invalid number {}", text))
+}
+
+fn address(text: &str, symbols: &Symbols) -> Result<u16, String> {
+    symbols.resolve(text).ok_or(format!("Invalid address or unknown label {}", text))
+}
+
+fn parse_pattern(text: &str) -> Result<Target, String> {
+    if text.len() != 4 {
+        return Err(format!("Opcode patterns have four digits, e.g. DXYN: {}", text));
+    }
+    let pattern = text.to_uppercase();
+    let (mut mask, mut value) = (0, 0);
+    for c in pattern.chars() {
+        mask <<= 4;
+        value <<= 4;
+        match c {
+            'X' | 'Y' | 'N' | 'K' => {}
+            _ => {
+                let digit = c.to_digit(16).ok_or(format!("Invalid opcode pattern {}", text))?;
+                mask |= 0xf;
+                value |= digit as u16;
+            }
+        }
+    }
+    Ok(Target::Opcode { pattern, mask, value })
+}
+
+fn parse_watch(words: &[&str], symbols: &Symbols) -> Result<Target, String> {
+    let (watch, words) = match words.first() {
+        Some(&"read") => (Watch::Read, &words[1..]),
+        Some(&"write") => (Watch::Write, &words[1..]),
+        Some(&"access") => (Watch::Access, &words[1..]),
+        _ => (Watch::Write, words),
+    };
+    match words {
+        [name] if access::parse_register(name).is_some() => Ok(Target::Reg(watch, access::parse_register(name).unwrap())),
+        [addr] => address(addr, symbols).map(|addr| Target::Mem(watch, addr, 1)),
+        [addr, len] => address(addr, symbols).and_then(|addr| number(len).map(|len| Target::Mem(watch, addr, len.max(1)))),
+        _ => Err("Expected watch [read|write|access] ADDR [LEN] or a register".to_string()),
+    }
+}
+
+//Parses "[ADDR|LABEL|op PATTERN|watch ...] [if EXPR]" into a target and a condition
+pub fn parse_spec(text: &str, symbols: &Symbols) -> Result<(Target, Option<(String, Expr)>), String> {
+    let text = format!(" {}", text);
+    let (spec, condition) = match text.find(" if ") {
+        Some(at) => {
+            let source = text[at + 4..].trim().to_string();
+            let parsed = expr::parse(&source)?;
+            (&text[..at], Some((source, parsed)))
+        }
+        None => (text.as_str(), None),
+    };
+    let words: Vec<&str> = spec.split_whitespace().collect();
+    let target = match words.as_slice() {
+        [] if condition.is_some() => Target::Always,
+        ["op", pattern] => parse_pattern(pattern)?,
+        ["watch", rest @ ..] => parse_watch(rest, symbols)?,
+        [addr] => Target::Addr(address(addr, symbols)?),
+        _ => return Err("Expected ADDR, op PATTERN, watch ... or if EXPR".to_string()),
+    };
+    Ok((target, condition))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watch_past_the_end_of_memory() {
+        let (target, _) = parse_spec("watch 0xfff 0xffff", &Symbols::default()).unwrap();
+        let breakpoint = Breakpoint { id: 1, target, condition: None, log: None };
+        assert_eq!(breakpoint.to_string(), "break on Write of 0xfff..0x10ffe");
+        let chip = crate::chip8::new_chip8();
+        let access = Access { mem_writes: Some((0xfff, 1)), ..Access::default() };
+        assert!(breakpoint.target.matches(&chip, &access));
+        let access = Access { mem_writes: Some((0xffe, 1)), ..Access::default() };
+        assert!(!breakpoint.target.matches(&chip, &access));
+    }
+}
@@ -1,13 +1,69 @@
 use std::collections::HashMap;
-use rand::Rng;
+use std::fmt;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 const START_ADDR: u16 = 0x200;
 const VIDEO_WIDTH: u32 = 64;
 const VIDEO_HEIGHT: u32 = 32;
 const FONTS_ADDR: u16 = 0x50;
+pub const MEM_SIZE: usize = 4096;
+
+//Behaviours that differ between the CHIP-8 interpreters games were written for
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Quirks {
+    pub shift_vy: bool,
+    //8xy6/8xyE shift Vy into Vx instead of shifting Vx in place
+    pub load_store_inc_i: bool,
+    //Fx55/Fx65 leave I pointing behind the last register
+    pub jump_vx: bool,
+    //Bnnn jumps to nnn + Vx (SCHIP's BXNN) instead of nnn + V0
+    pub vf_reset: bool,
+    //8xy1/8xy2/8xy3 reset VF to 0
+}
+
+impl Quirks {
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name.to_lowercase().as_str() {
+            "chip8" | "vip" => Some(Quirks { shift_vy: true, load_store_inc_i: true, jump_vx: false, vf_reset: true }),
+            "schip" => Some(Quirks { shift_vy: false, load_store_inc_i: false, jump_vx: true, vf_reset: false }),
+            "modern" => Some(Quirks::default()),
+            _ => None,
+        }
+    }
+
+    //Name of the preset these quirks are equal to
+    pub fn name(&self) -> Option<&'static str> {
+        ["chip8", "schip", "modern"].iter().copied().find(|name| Quirks::preset(name) == Some(*self))
+    }
+}
 
 
+//Errors that stop the machine; pc is left pointing at the faulting instruction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    InvalidOpcode(u16, u16),
+    //pc, opcode
+    StackOverflow(u16),
+    StackUnderflow(u16),
+    PcOutOfBounds(u16),
+    MemoryOutOfBounds(u16, u16),
+    //pc, address
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::InvalidOpcode(pc, opcode) => write!(f, "invalid opcode {:04x} at {:#05x}", opcode, pc),
+            Fault::StackOverflow(pc) => write!(f, "stack overflow at {:#05x}", pc),
+            Fault::StackUnderflow(pc) => write!(f, "stack underflow at {:#05x}", pc),
+            Fault::PcOutOfBounds(pc) => write!(f, "program counter out of memory at {:#05x}", pc),
+            Fault::MemoryOutOfBounds(pc, addr) => write!(f, "access to {:#x} out of memory at {:#05x}", addr, pc),
+        }
+    }
+}
 
+#[derive(Clone)]
 struct Dispatcher {
     main_table: HashMap<u16, fn(&mut Chip8)>,
     table_0: HashMap<u16, fn(&mut Chip8)>,
@@ -18,28 +74,36 @@ struct Dispatcher {
 
 }
 
+#[derive(Clone)]
 pub struct Chip8 {
-    reg: [u8; 16],
+    pub reg: [u8; 16],
     //Registers for the CPU
-    mem: [u8; 4096],
+    pub mem: [u8; MEM_SIZE],
     //4KB of memory
-    opcode: u16,
+    pub opcode: u16,
     //current opcode
-    i: u16,
+    pub i: u16,
     //stores memory address for use in operations
-    pc: u16,
+    pub pc: u16,
     //Program counter
-    stack: [u16; 16],
+    pub stack: [u16; 16],
     //Stack
-    sp: u8,
+    pub sp: u8,
     //Stack pointer
-    delay: u8,
+    pub delay: u8,
     //delay timer;
-    sound: u8,
+    pub sound: u8,
     //sound timer; when the it's 0, a buzz shall be emitted
     pub gfx: [u8; (VIDEO_HEIGHT * VIDEO_WIDTH) as usize],
-    keypad: [u8; 16],
+    pub dirty: bool,
+    //gfx changed since the frontend last drew it
+    pub keypad: [u8; 16],
     dispatcher: Dispatcher,//Monochrome display memory
+    pub quirks: Quirks,
+    rng: StdRng,
+    //source of CXKK; seeded for reproducible runs
+    fault: Option<Fault>,
+    //set by an instruction that could not be executed
 }
 
 
@@ -65,9 +129,7 @@ impl Chip8 {
             0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
             0xF0, 0x80, 0xF0, 0x80, 0x80  // F
         ];
-        for i in 0..80 {
-            self.mem[0x50 + i] = fonts[i];
-        }
+        self.mem[FONTS_ADDR as usize..FONTS_ADDR as usize + fonts.len()].copy_from_slice(&fonts);
 
         //Setting up the dispatcher tables; the goal of the dispatcher tables is to make the call of a function relatively easy
         self.dispatcher.main_table.insert(0x1, Chip8::op_1nnn);
@@ -95,8 +157,8 @@ impl Chip8 {
         self.dispatcher.table_8.insert(0xe, Chip8::op_8xye);
 
         self.dispatcher.main_table.insert(0x0, Chip8::table_0);
-        self.dispatcher.table_0.insert(0xe0, Chip8::op_00e0);
-        self.dispatcher.table_0.insert(0xee, Chip8::op_00ee);
+        self.dispatcher.table_0.insert(0x00e0, Chip8::op_00e0);
+        self.dispatcher.table_0.insert(0x00ee, Chip8::op_00ee);
 
         self.dispatcher.main_table.insert(0xe, Chip8::table_e);
         self.dispatcher.table_e.insert(0x9e, Chip8::op_ex9e);
@@ -115,32 +177,78 @@ impl Chip8 {
         self.dispatcher.table_f.insert(0x65, Chip8::op_fx65);
     }
 
-    pub fn set_key(&mut self, key: u8, is_set: bool){
-        if is_set == true {
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    //Width and height of gfx in pixels
+    pub fn resolution(&self) -> [usize; 2] {
+        [VIDEO_WIDTH as usize, VIDEO_HEIGHT as usize]
+    }
+
+    pub fn sound_active(&self) -> bool {
+        self.sound > 0
+    }
 
+    pub fn set_key(&mut self, key: u8, is_set: bool){
+        if is_set {
             self.keypad[key as usize] = 1;
         } else {
-
             self.keypad[key as usize] = 0;
         }
     }
 
     pub fn load_rom(&mut self, data: &[u8]) {
-        for i in 0..data.len() {
-            self.mem[START_ADDR as usize + i] = data[i];
-        }
+        self.load_rom_at(data, START_ADDR);
     }
 
-    pub fn cycle(&mut self) {
-        //Fetch
+    //Some roms (e.g. for the ETI 660) expect to be loaded and started at another address than 0x200
+    pub fn load_rom_at(&mut self, data: &[u8], addr: u16) {
+        //An address outside of memory loads nothing, the machine faults on its first instruction
+        let len = data.len().min(self.mem.len().saturating_sub(addr as usize));
+        self.mem[addr as usize..addr as usize + len].copy_from_slice(&data[..len]);
+        self.pc = addr;
+    }
 
-        #![allow(arithmetic_overflow)]
+    pub fn cycle(&mut self) -> Result<(), Fault> {
+        //Fetch
+        if self.pc as usize + 1 >= self.mem.len() {
+            return Err(Fault::PcOutOfBounds(self.pc));
+        }
         self.opcode = ((self.mem[self.pc as usize] as u16) << 8) | self.mem[(self.pc + 1) as usize] as u16;
-        // self.opcode = self.mem[self.pc as usize] as u16;
         //Increment PC
         self.pc += 2;
         //Execute
         self.main_table();
+
+        match self.fault.take() {
+            Some(fault) => {
+                self.pc -= 2;
+                Err(fault)
+            }
+            None => Ok(()),
+        }
+    }
+
+    //Memory accesses of I based instructions are checked, they would panic otherwise
+    fn check_mem(&mut self, addr: u16, len: u16) -> bool {
+        if addr as usize + len as usize > self.mem.len() {
+            self.fault = Some(Fault::MemoryOutOfBounds(self.pc - 2, addr.saturating_add(len.max(1) - 1)));
+            return false;
+        }
+        true
+    }
+
+    fn invalid_opcode(&mut self) {
+        self.fault = Some(Fault::InvalidOpcode(self.pc - 2, self.opcode));
+    }
+
+    //Called at 60Hz, independent of the instruction rate
+    pub fn tick_timers(&mut self) {
         //Delay
         if self.delay > 0 {
             self.delay -= 1;
@@ -153,32 +261,51 @@ impl Chip8 {
     }
 
     fn main_table(&mut self) {
-        self.dispatcher.main_table[&(self.opcode >> 12)](self);
+        match self.dispatcher.main_table.get(&(self.opcode >> 12)) {
+            Some(op) => op(self),
+            None => self.invalid_opcode(),
+        }
     }
 
     fn table_0(&mut self) {
-        self.dispatcher.table_0[&(self.opcode & 0xff)](self);
+        //0NNN (SYS) calls machine code and can't be emulated
+        match self.dispatcher.table_0.get(&self.opcode) {
+            Some(op) => op(self),
+            None => self.invalid_opcode(),
+        }
     }
 
     fn table_8(&mut self) {
-        self.dispatcher.table_8[&(self.opcode & 0xf)](self);
+        match self.dispatcher.table_8.get(&(self.opcode & 0xf)) {
+            Some(op) => op(self),
+            None => self.invalid_opcode(),
+        }
     }
 
     fn table_e(&mut self) {
-        self.dispatcher.table_e[&(self.opcode & 0xff)](self);
+        match self.dispatcher.table_e.get(&(self.opcode & 0xff)) {
+            Some(op) => op(self),
+            None => self.invalid_opcode(),
+        }
     }
 
     fn table_f(&mut self) {
-        self.dispatcher.table_f[&(self.opcode & 0xff)](self);
+        match self.dispatcher.table_f.get(&(self.opcode & 0xff)) {
+            Some(op) => op(self),
+            None => self.invalid_opcode(),
+        }
     }
 
 
     fn op_00e0(&mut self) { //CLS. Clear the display
-        for mut x in self.gfx.iter(){
-            x = &0;
-        }
+        self.gfx = [0; (VIDEO_HEIGHT * VIDEO_WIDTH) as usize];
+        self.dirty = true;
     }
     fn op_00ee(&mut self) { //RET
+        if self.sp == 0 {
+            self.fault = Some(Fault::StackUnderflow(self.pc - 2));
+            return;
+        }
         self.sp -= 1;
         self.pc = self.stack[self.sp as usize];
     }
@@ -188,6 +315,10 @@ impl Chip8 {
     }
     fn op_2nnn(&mut self) { //CALL addr
         let addr = self.opcode & 0xfff;
+        if self.sp as usize >= self.stack.len() {
+            self.fault = Some(Fault::StackOverflow(self.pc - 2));
+            return;
+        }
         self.stack[self.sp as usize] = self.pc;
         self.pc = addr;
         self.sp += 1;
@@ -239,18 +370,27 @@ impl Chip8 {
         let vy = (self.opcode & 0xf0) >> 4;
 
         self.reg[vx as usize] |= self.reg[vy as usize];
+        if self.quirks.vf_reset {
+            self.reg[0xf] = 0;
+        }
     }
     fn op_8xy2(&mut self) { //AND Vx, Vy. Set Vx = Vx AND Vy
         let vx = (self.opcode & 0xf00) >> 8;
         let vy = (self.opcode & 0xf0) >> 4;
 
         self.reg[vx as usize] &= self.reg[vy as usize];
+        if self.quirks.vf_reset {
+            self.reg[0xf] = 0;
+        }
     }
     fn op_8xy3(&mut self) { //XOR Vx, Vy. Set Vx = Vx XOR Vy.
         let vx = (self.opcode & 0xf00) >> 8;
         let vy = (self.opcode & 0xf0) >> 4;
 
         self.reg[vx as usize] ^= self.reg[vy as usize];
+        if self.quirks.vf_reset {
+            self.reg[0xf] = 0;
+        }
     }
     fn op_8xy4(&mut self) { //ADD Vx, Vy. Set Vx = Vx + Vy, set VF = carry.
         let vx = (self.opcode & 0xf00) >> 8;
@@ -289,15 +429,17 @@ impl Chip8 {
 
         let vx = (self.opcode & 0xf00) >> 8;
         let vy = (self.opcode & 0xf0) >> 4;
+        let src = if self.quirks.shift_vy { vy } else { vx };
 
-        self.reg[0xf] = self.reg[vx as usize] & 0x1;
-        self.reg[vy as usize] = self.reg[vx as usize] >> 1;
+        let value = self.reg[src as usize];
+        self.reg[vx as usize] = value >> 1;
+        self.reg[0xf] = value & 0x1;
     }
     fn op_8xy7(&mut self) {//SUBN Vx, Vy. Set Vx = Vy - Vx, set VF = NOT borrow.
         let vx = (self.opcode & 0xf00) >> 8;
         let vy = (self.opcode & 0xf0) >> 4;
 
-        let diff = self.reg[vy as usize] as u16 - self.reg[vx as usize] as u16;
+        let diff = (self.reg[vy as usize] as u16).wrapping_sub(self.reg[vx as usize] as u16);
 
         if self.reg[vy as usize] > self.reg[vx as usize] {
             self.reg[0xf] = 1;
@@ -317,9 +459,11 @@ impl Chip8 {
 
         let vx = (self.opcode & 0xf00) >> 8;
         let vy = (self.opcode & 0xf0) >> 4;
+        let src = if self.quirks.shift_vy { vy } else { vx };
 
-        self.reg[0xf] = self.reg[vx as usize] & 0x1;
-        self.reg[vy as usize] = self.reg[vx as usize] << 1;
+        let value = self.reg[src as usize];
+        self.reg[vx as usize] = value << 1;
+        self.reg[0xf] = (value & 0x80) >> 7;
     }
 
     fn op_9xy0(&mut self) { //SNE Vx, Vy. Skip next instruction if Vx != Vy.
@@ -333,13 +477,14 @@ impl Chip8 {
         self.i = self.opcode & 0xfff;
     }
     fn op_bnnn(&mut self) { //JP V0, addr. Jump to location nnn + V0
-        self.pc = self.opcode & 0xfff + self.reg[0] as u16;
+        let vx = if self.quirks.jump_vx { (self.opcode & 0xf00) >> 8 } else { 0 };
+        self.pc = (self.opcode & 0xfff) + self.reg[vx as usize] as u16;
 
     }
     fn op_cxkk(&mut self) { //RND Vx, byte. Set Vx = random byte AND kk
 
         let vx = (self.opcode & 0xf00) >> 8;
-        let rand_byte: u8 = rand::thread_rng().gen_range(0..=255);
+        let rand_byte: u8 = self.rng.gen_range(0..=255);
 
         self.reg[vx as usize] = rand_byte & (self.opcode & 0xff) as u8;
     }
@@ -348,6 +493,11 @@ impl Chip8 {
         let vy = (self.opcode & 0xf0) >> 4;
         let height = self.opcode & 0xf;
 
+        if !self.check_mem(self.i, height) {
+            return;
+        }
+
+        self.dirty = true;
         let x_pos = self.reg[vx as usize] as u32 % VIDEO_WIDTH;
         let y_pos = self.reg[vy as usize] as u32 % VIDEO_HEIGHT;
         self.reg[0xf] = 0;
@@ -357,7 +507,7 @@ impl Chip8 {
 
             for col in 0..8 {
                 let sprite_pixel = sprite_byte & (0x80u8 >> col); //extracts the colth bit of the row byte
-                let screen_pixel: u32 = (y_pos + row as u32) as u32 * VIDEO_WIDTH + x_pos as u32 + col as u32;
+                let screen_pixel: u32 = (y_pos + row as u32) * VIDEO_WIDTH + x_pos + col as u32;
 
                 //FIXME Ugly hack
                 if screen_pixel >= self.gfx.len() as u32{
@@ -379,7 +529,7 @@ impl Chip8 {
     fn op_ex9e(&mut self) { //SKP Vx Skip next instruction if key with the value of Vx is pressed.
         let vx = (self.opcode & 0xf00) >> 8;
 
-        let key = self.reg[vx as usize];
+        let key = self.reg[vx as usize] & 0xf;
 
         if self.keypad[key as usize] != 0 {
             self.pc += 2;
@@ -388,7 +538,7 @@ impl Chip8 {
     fn op_exa1(&mut self) { //SKNP Vx. Skip next instruction if key with the value of Vx is not pressed.
         let vx = (self.opcode & 0xf00) >> 8;
 
-        let key = self.reg[vx as usize];
+        let key = self.reg[vx as usize] & 0xf;
 
         if self.keypad[key as usize] == 0{
             self.pc += 2;
@@ -411,7 +561,7 @@ impl Chip8 {
                 break;
             }
         }
-        if flag == false {
+        if !flag {
             self.pc -= 2;
         }
     }
@@ -425,7 +575,7 @@ impl Chip8 {
     }
     fn op_fx1e(&mut self) {//ADD I, Vx. Set I = I + Vx.
         let vx = (self.opcode & 0xf00) >> 8;
-        self.i += self.reg[vx as usize] as u16;
+        self.i = self.i.wrapping_add(self.reg[vx as usize] as u16);
     }
     fn op_fx29(&mut self) {//LD F, Vx. Set I = location of sprite for digit Vx.
 
@@ -435,6 +585,9 @@ impl Chip8 {
     }
     fn op_fx33(&mut self) {//LD B, Vx. Store BCD representation of Vx in memory locations I, I+1, and I+2.
         let vx = (self.opcode & 0xf00) >> 8;
+        if !self.check_mem(self.i, 3) {
+            return;
+        }
         let mut value = self.reg[vx as usize];
         self.mem[(self.i + 2) as usize] = value % 10;
         value /= 10;
@@ -444,20 +597,33 @@ impl Chip8 {
     }
     fn op_fx55(&mut self) {//LD [I], Vx. Store registers V0 through Vx in memory starting at location I.
         let vx = (self.opcode & 0xf00) >> 8;
+        if !self.check_mem(self.i, vx + 1) {
+            return;
+        }
         for (index, r) in self.reg.iter().enumerate() {
             self.mem[self.i as usize + index] = *r;
             if vx as usize == index {
                 break;
             }
         }
+        if self.quirks.load_store_inc_i {
+            self.i += vx + 1;
+        }
     }
     fn op_fx65(&mut self) {//LD Vx, [I]. Read registers V0 through Vx from memory starting at location I.
 
         let vx = (self.opcode & 0xf00) >> 8;
+        if !self.check_mem(self.i, vx + 1) {
+            return;
+        }
         for i in 0..=vx {
-           self.reg[i as usize] = self.mem[(self.i + i as u16) as usize];
+           self.reg[i as usize] = self.mem[(self.i + i) as usize];
+        }
+        if self.quirks.load_store_inc_i {
+            self.i += vx + 1;
         }
     }
+    #[allow(dead_code)]
     pub fn chip8_says_hello(&self) {
         println!("Chip 8 says hello");
     }
@@ -485,27 +651,29 @@ impl Chip8 {
         }
     }
 
+    #[allow(dead_code)]
     pub fn print_memory(&self) {
         //print memory in 256 bytes blocks; first two blocks are fw blocks
         println!("----MEMORY----");
         for i in 0..self.mem.len(){
             print!("0x{:02x} ", self.mem[i]);
             if i % 16 == 15 {
-                print!("\n");
+                println!();
             }
             if i % 256 == 255 {
-                print!("\n");
+                println!();
                 println!("-0x{:02x}", i+1);
             }
         }
     }
 
+    #[allow(dead_code)]
     pub fn print_gfx(&self) {
         println!("-----GFX-----");
         for i in 0..self.gfx.len(){
-            print!("0x{:02x} ", self.mem[i]);
+            print!("0x{:02x} ", self.gfx[i]);
             if i % 64 == 63 {
-                print!("\n");
+                println!();
             }
         }
     }
@@ -514,7 +682,7 @@ impl Chip8 {
 pub fn new_chip8() -> Chip8 {
     let mut new_chip = Chip8 {
         reg: [0; 16],
-        mem: [0; 4096],
+        mem: [0; MEM_SIZE],
         opcode: 0,
         i: 0,
         pc: 0,
@@ -523,36 +691,88 @@ pub fn new_chip8() -> Chip8 {
         delay: 0,
         sound: 0,
         gfx: [0; 32 * 64],
+        dirty: true,
         keypad: [0; 16],
         dispatcher: new_dispatcher(),
+        quirks: Quirks::default(),
+        rng: StdRng::from_entropy(),
+        fault: None,
     };
     new_chip.init();
-    return new_chip;
+    new_chip
 }
 
 fn new_dispatcher() -> Dispatcher {
-    let mut new_dispatcher = Dispatcher {
+    Dispatcher {
         main_table: HashMap::new(),
         table_0: HashMap::new(),
         table_8: HashMap::new(),
         table_e: HashMap::new(),
         table_f: HashMap::new(),
-
-    };
-
-    return new_dispatcher;
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    //Runs the instructions from the start address with the registers set beforehand
+    fn run(code: &[u8], reg: &[(usize, u8)]) -> Chip8 {
+        let mut chip = new_chip8();
+        chip.load_rom(code);
+        for (n, value) in reg {
+            chip.reg[*n] = *value;
+        }
+        for _ in 0..code.len() / 2 {
+            chip.cycle().unwrap();
+        }
+        chip
+    }
 
+    #[test]
+    fn bnnn_adds_v0_to_the_address() {
+        let chip = run(&[0xb3, 0x00], &[(0, 0x12)]);
+        assert_eq!(chip.pc, 0x312);
+    }
 
+    #[test]
+    fn subn_wraps_and_sets_no_borrow() {
+        let chip = run(&[0x81, 0x27], &[(1, 5), (2, 3)]);
+        assert_eq!(chip.reg[1], 0xfe);
+        assert_eq!(chip.reg[0xf], 0);
+        let chip = run(&[0x81, 0x27], &[(1, 3), (2, 5)]);
+        assert_eq!(chip.reg[1], 2);
+        assert_eq!(chip.reg[0xf], 1);
+    }
 
+    #[test]
+    fn load_address_outside_of_memory() {
+        let mut chip = new_chip8();
+        chip.load_rom_at(&[0x12, 0x00], 0x1000);
+        assert_eq!(chip.cycle(), Err(Fault::PcOutOfBounds(0x1000)));
+    }
 
+    #[test]
+    fn index_at_the_top_of_the_address_space() {
+        let mut chip = new_chip8();
+        chip.load_rom(&[0xf0, 0x1e]);
+        chip.i = 0xffff;
+        chip.reg[0] = 2;
+        chip.cycle().unwrap();
+        assert_eq!(chip.i, 1);
 
+        let mut chip = new_chip8();
+        chip.load_rom(&[0xf1, 0x55]);
+        chip.i = 0xffff;
+        assert_eq!(chip.cycle(), Err(Fault::MemoryOutOfBounds(0x200, 0xffff)));
+    }
 
-
-
-
-
-
-
+    #[test]
+    fn cls_clears_the_screen() {
+        //Draws the font's 0 and clears it again
+        let chip = run(&[0xa0, 0x50, 0xd0, 0x05, 0x00, 0xe0], &[]);
+        assert!(chip.gfx.iter().all(|pixel| *pixel == 0));
+        let chip = run(&[0xa0, 0x50, 0xd0, 0x05], &[]);
+        assert!(chip.gfx.iter().any(|pixel| *pixel != 0));
+    }
+}
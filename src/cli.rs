@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use clap::{value_parser, Arg, ArgMatches, Command};
+use serde::{Deserialize, Serialize};
+
+use crate::keymap;
+use crate::palette;
+use crate::trace;
+
+pub const QUIRK_PRESETS: [&str; 4] = ["chip8", "vip", "schip", "modern"];
+pub const SCALINGS: [&str; 2] = ["integer", "smooth"];
+const MAX_SCALE: f64 = 100.0;
+
+//Settings that can be given on the command line or in the config file; the command line wins
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Options {
+    pub scale: Option<f64>,
+    pub ips: Option<u32>,
+    pub quirks: Option<String>,
+    #[serde(default, deserialize_with = "palette::deserialize", serialize_with = "palette::serialize", skip_serializing_if = "Option::is_none")]
+    pub palette: Option<Vec<String>>,
+    //a palette name or colors
+    pub seed: Option<u64>,
+    pub keymap: Option<String>,
+    pub keys: Option<HashMap<String, u8>>,
+    pub fullscreen: Option<bool>,
+    pub scaling: Option<String>,
+    pub mute: Option<bool>,
+    pub rom_dir: Option<String>,
+    pub symbols: Option<String>,
+    pub flicker: Option<String>,
+    pub screenshot_scale: Option<u32>,
+    pub keypad: Option<bool>,
+}
+
+impl Options {
+    //Fills the fields missing in self from a lower priority source
+    pub fn merge(&mut self, lower: &Options) {
+        let lower = lower.clone();
+        self.scale = self.scale.or(lower.scale);
+        self.ips = self.ips.or(lower.ips);
+        self.quirks = self.quirks.take().or(lower.quirks);
+        self.palette = self.palette.take().or(lower.palette);
+        self.seed = self.seed.or(lower.seed);
+        self.keymap = self.keymap.take().or(lower.keymap);
+        self.keys = self.keys.take().or(lower.keys);
+        self.fullscreen = self.fullscreen.or(lower.fullscreen);
+        self.scaling = self.scaling.take().or(lower.scaling);
+        self.mute = self.mute.or(lower.mute);
+        self.rom_dir = self.rom_dir.take().or(lower.rom_dir);
+        self.symbols = self.symbols.take().or(lower.symbols);
+        self.flicker = self.flicker.take().or(lower.flicker);
+        self.screenshot_scale = self.screenshot_scale.or(lower.screenshot_scale);
+        self.keypad = self.keypad.or(lower.keypad);
+    }
+}
+
+//Window scales are positive, up to a pixel the size of a screen
+pub fn check_scale(scale: f64) -> Result<f64, String> {
+    if !(scale > 0.0 && scale <= MAX_SCALE) {
+        return Err(format!("The scale goes from above 0 to {}, got {}", MAX_SCALE, scale));
+    }
+    Ok(scale)
+}
+
+fn parse_scale(text: &str) -> Result<f64, String> {
+    text.parse().map_err(|_| format!("Invalid scale {}", text)).and_then(check_scale)
+}
+
+fn rom_arg(required: bool) -> Arg<'static> {
+    Arg::new("ROM").required(required).help("Path of the rom")
+}
+
+//Options of the window, for running without a subcommand and with run
+fn window_args() -> [Arg<'static>; 4] {
+    [
+        Arg::new("scale").long("scale").takes_value(true)
+            .value_parser(parse_scale)
+            .help("Size of a chip8 pixel in the window"),
+        Arg::new("fullscreen").long("fullscreen")
+            .help("Start in fullscreen mode"),
+        Arg::new("scaling").long("scaling").takes_value(true)
+            .value_parser(SCALINGS)
+            .help("Scale the screen to the window in whole multiples or smoothly to any size [default: integer]"),
+        Arg::new("keypad").long("keypad")
+            .help("Show a hex keypad that can be clicked or touched"),
+    ]
+}
+
+pub fn build() -> Command<'static> {
+    Command::new("rust_8")
+        .about("A chip8 emulator implemented in rust")
+        .arg(rom_arg(false))
+        .args(window_args())
+        .arg(Arg::new("ips").long("ips").takes_value(true).global(true)
+            .value_parser(value_parser!(u32).range(1..))
+            .help("Instructions executed per second"))
+        .arg(Arg::new("quirks").long("quirks").value_name("PRESET").takes_value(true).global(true)
+            .value_parser(QUIRK_PRESETS)
+            .help("Interpreter behaviour to emulate"))
+        .arg(Arg::new("palette").long("palette").value_name("NAME|COLORS").takes_value(true).global(true)
+            .help("classic, amber, green, octo, lcd or the background and foreground colors, e.g. #000000,#ffffff; 4 colors set the XO-CHIP planes too"))
+        .arg(Arg::new("seed").long("seed").takes_value(true).global(true)
+            .value_parser(value_parser!(u64))
+            .help("Seed of the random number generator"))
+        .arg(Arg::new("flicker").long("flicker").value_name("MODE").takes_value(true).global(true)
+            .help("Anti-flicker mode: off, vblank, blend or decay[:FACTOR], e.g. decay:0.6"))
+        .arg(Arg::new("screenshot-scale").long("screenshot-scale").value_name("N").takes_value(true).global(true)
+            .value_parser(value_parser!(u32).range(1..))
+            .help("Size of a chip8 pixel in screenshots and recordings [default: 1]"))
+        .arg(Arg::new("keymap").long("keymap").takes_value(true).global(true)
+            .value_parser(keymap::PRESETS)
+            .help("Keyboard layout of the hex keypad [default: qwertz]"))
+        .arg(Arg::new("mute").long("mute").global(true)
+            .help("Don't sound the beeper"))
+        .arg(Arg::new("rom-dir").long("rom-dir").takes_value(true).global(true)
+            .help("Directory listed by the rom library browser"))
+        .arg(Arg::new("symbols").long("symbols").value_name("FILE").takes_value(true).global(true)
+            .help("Labels and source lines of the rom: an assembly source, Octo's JSON or ADDR NAME lines [default: ROM.sym]"))
+        .arg(Arg::new("debug").long("debug").global(true)
+            .help("Start paused with a debugger prompt on the terminal"))
+        .arg(Arg::new("dap").long("dap").value_name("stdio|PORT").takes_value(true).global(true)
+            .conflicts_with("debug")
+            .help("Serve the Debug Adapter Protocol on stdio or a local TCP port"))
+        .arg(Arg::new("trace").long("trace").value_name("FILE").takes_value(true).global(true)
+            .help("Write a line per executed instruction to FILE"))
+        .arg(Arg::new("trace-format").long("trace-format").value_name("COLUMNS").takes_value(true).global(true)
+            .default_value(trace::DEFAULT_FORMAT)
+            .help("Columns of the trace: cycle, pc, opcode, mnemonic, label, v (v0 to vf), a register like v3 or a range like v0-v7, i, sp, dt, st"))
+        .arg(Arg::new("trace-ring").long("trace-ring").value_name("N").takes_value(true).global(true)
+            .value_parser(value_parser!(usize))
+            .help("Only keep the last N instructions of the trace, written on a fault or at exit"))
+        .arg(Arg::new("profile").long("profile").global(true)
+            .help("Count the instructions executed per address and subroutine and print the hot spots at exit"))
+        .arg(Arg::new("flamegraph").long("flamegraph").value_name("FILE").takes_value(true).global(true)
+            .help("Write the profile as collapsed stacks for flamegraph at exit"))
+        .arg(Arg::new("callgrind").long("callgrind").value_name("FILE").takes_value(true).global(true)
+            .help("Write the profile in the callgrind format of KCachegrind at exit"))
+        .arg(Arg::new("coverage").long("coverage").value_name("FILE").takes_value(true).global(true)
+            .help("Add the executed instructions and skips of the run to the coverage data in FILE at exit"))
+        .arg(Arg::new("record").long("record").value_name("FILE").takes_value(true).global(true)
+            .help("Record the screen to an animated GIF or PNG (.gif, .png or .apng) and the beeper to a WAV next to it"))
+        .arg(Arg::new("config").long("config").value_name("FILE").takes_value(true).global(true)
+            .help("TOML file with default and per rom options [default: ~/.config/rust_8/config.toml]"))
+        .subcommand(Command::new("run")
+            .about("Runs a rom in a window, or opens the rom library without one")
+            .arg(rom_arg(false))
+            .args(window_args()))
+        .subcommand(Command::new("headless")
+            .about("Runs a rom without a window and prints the final screen")
+            .arg(rom_arg(true))
+            .arg(Arg::new("frames").long("frames").takes_value(true).default_value("600")
+                .value_parser(value_parser!(u32))
+                .help("Number of 60Hz frames to run"))
+            .arg(Arg::new("heatmap").long("heatmap").value_name("FILE").takes_value(true)
+                .help("Write a PNG of the memory reads, writes and executes at the end"))
+            .arg(Arg::new("input").long("input").value_name("FILE").takes_value(true)
+                .help("Press keys at given frames, lines of FRAME press|release KEY"))
+            .arg(Arg::new("screenshot").long("screenshot").value_name("FILE").takes_value(true)
+                .help("Write a PNG of the screen at the end")))
+        .subcommand(Command::new("term")
+            .about("Runs a rom in the terminal, drawn with half block characters")
+            .arg(rom_arg(true))
+            .arg(Arg::new("braille").long("braille")
+                .help("Draw 2x4 pixels per character with braille dots for small terminals")))
+        .subcommand(Command::new("disasm")
+            .about("Disassembles a rom")
+            .arg(rom_arg(true)))
+        .subcommand(Command::new("asm")
+            .about("Assembles a source file into a rom")
+            .arg(Arg::new("SOURCE").required(true))
+            .arg(Arg::new("output").short('o').long("output").takes_value(true)
+                .help("Path of the rom, defaults to the source with a .ch8 extension"))
+            .arg(Arg::new("map").long("map")
+                .help("Also write the labels and source lines to a .sym file next to the rom")))
+        .subcommand(Command::new("tracediff")
+            .about("Reports the first instruction where two traces differ")
+            .arg(Arg::new("A").required(true))
+            .arg(Arg::new("B").required(true))
+            .arg(Arg::new("context").long("context").value_name("N").takes_value(true)
+                .value_parser(value_parser!(usize))
+                .help("Instructions shown before and after the difference [default: 5]")))
+        .subcommand(Command::new("coverage")
+            .about("Prints the disassembly of a rom annotated with the coverage data of one or more runs")
+            .arg(rom_arg(true))
+            .arg(Arg::new("DATA").required(true).multiple_values(true)
+                .help("Coverage files written with --coverage, they are merged"))
+            .arg(Arg::new("lcov").long("lcov").value_name("FILE").takes_value(true)
+                .help("Also write an lcov tracefile of the source lines, needs the symbols of the rom")))
+        .subcommand(Command::new("info")
+            .about("Prints the metadata of a rom and its companion .txt")
+            .arg(rom_arg(true)))
+}
+
+//The options of the window given to the top level command or to run
+pub fn window_options(matches: &ArgMatches) -> Options {
+    Options {
+        scale: matches.get_one::<f64>("scale").copied(),
+        fullscreen: if matches.is_present("fullscreen") { Some(true) } else { None },
+        scaling: matches.get_one::<String>("scaling").cloned(),
+        keypad: if matches.is_present("keypad") { Some(true) } else { None },
+        ..Options::default()
+    }
+}
+
+pub fn options(matches: &ArgMatches) -> Options {
+    let window = window_options(matches);
+    Options {
+        scale: window.scale,
+        ips: matches.get_one::<u32>("ips").copied(),
+        quirks: matches.get_one::<String>("quirks").cloned(),
+        palette: matches.get_one::<String>("palette").map(|p| p.split(',').map(|c| c.trim().to_string()).collect()),
+        seed: matches.get_one::<u64>("seed").copied(),
+        keymap: matches.get_one::<String>("keymap").cloned(),
+        keys: None,
+        fullscreen: window.fullscreen,
+        scaling: window.scaling,
+        mute: if matches.is_present("mute") { Some(true) } else { None },
+        rom_dir: matches.get_one::<String>("rom-dir").cloned(),
+        symbols: matches.get_one::<String>("symbols").cloned(),
+        flicker: matches.get_one::<String>("flicker").cloned(),
+        screenshot_scale: matches.get_one::<u32>("screenshot-scale").copied(),
+        keypad: window.keypad,
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::cli::Options;
+
+//~/.config/rust_8/config.toml holds global defaults for the options of the command line and
+//[rom."<sha1 or file name>"] sections overriding them for single games:
+//
+//  ips = 700
+//  quirks = "chip8"
+//  palette = "amber"
+//
+//  [rom."f13766c14aeb02ad8d4d103cb5eadd282d20cddc"]
+//  ips = 900
+//  palette = ["#000000", "#ff8c00"]
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(flatten)]
+    pub defaults: Options,
+    #[serde(default)]
+    pub rom: HashMap<String, Options>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl Config {
+    //The section for the hash wins over the one for the file name
+    pub fn rom_options(&self, hash: &str, file_name: &str) -> Options {
+        let mut options = self.rom.get(hash).cloned().unwrap_or_default();
+        if let Some(by_name) = self.rom.get(file_name) {
+            options.merge(by_name);
+        }
+        options
+    }
+
+    //Writes the settings into the [rom."<hash>"] section of the config file.
+    //The file is rewritten from its parsed contents, so comments are lost.
+    pub fn save_rom_options(&mut self, hash: &str, options: &Options) -> Result<PathBuf, String> {
+        let path = self.path.clone().or_else(default_path).ok_or("No config directory")?;
+        let mut document = match fs::read_to_string(&path) {
+            Ok(text) => text.parse::<toml::Value>().map_err(|e| format!("Error parsing {}: {}", path.display(), e))?,
+            Err(_) => toml::Value::Table(toml::value::Table::new()),
+        };
+
+        let section = toml::Value::try_from(options).map_err(|e| e.to_string())?;
+        let table = document.as_table_mut().ok_or("The config is no table")?;
+        let roms = table.entry("rom").or_insert_with(|| toml::Value::Table(toml::value::Table::new()));
+        roms.as_table_mut().ok_or("rom is no table")?.insert(hash.to_string(), section);
+
+        let text = toml::to_string_pretty(&document).map_err(|e| e.to_string())?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        fs::write(&path, text).map_err(|e| format!("Error writing {}: {}", path.display(), e))?;
+
+        self.rom.insert(hash.to_string(), options.clone());
+        Ok(path)
+    }
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    if let Ok(dir) = env::var("XDG_CONFIG_HOME") {
+        return Some(PathBuf::from(dir).join("rust_8"));
+    }
+    env::var("HOME").ok().map(|home| PathBuf::from(home).join(".config").join("rust_8"))
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+//Loads the given config file, or the default one if it exists
+pub fn load(path: Option<&Path>) -> Result<Config, String> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => match default_path() {
+            Some(path) if path.is_file() => path,
+            other => return Ok(Config { path: other, ..Config::default() }),
+        },
+    };
+
+    let text = fs::read_to_string(&path).map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+    let mut config: Config = toml::from_str(&text).map_err(|e| format!("Error parsing {}: {}", path.display(), e))?;
+    config.path = Some(path);
+    Ok(config)
+}
@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use crate::disasm;
+use crate::symbols::Symbols;
+
+//Executed instructions and skip branches of a rom. The data file has one fact per line, so files from
+//several runs of the same rom merge by adding them up:
+//  rom SHA1
+//  exec ADDR COUNT
+//  branch ADDR TAKEN NOT_TAKEN
+#[derive(Default)]
+pub struct Coverage {
+    rom: Option<String>,
+    //SHA-1 of the rom the counts belong to
+    executed: BTreeMap<u16, u64>,
+    branches: BTreeMap<u16, (u64, u64)>,
+}
+
+//3XKK, 4XKK, 5XY0, 9XY0, EX9E and EXA1 skip the next instruction or not
+fn is_skip(opcode: u16) -> bool {
+    match opcode >> 12 {
+        0x3 | 0x4 => true,
+        0x5 | 0x9 => opcode & 0xf == 0,
+        0xe => matches!(opcode & 0xff, 0x9e | 0xa1),
+        _ => false,
+    }
+}
+
+impl Coverage {
+    //Starts counting for a rom, the counts of another rom are dropped
+    pub fn start(&mut self, hash: &str) {
+        if self.rom.as_deref() != Some(hash) {
+            if self.rom.is_some() {
+                println!("Coverage data of another rom is dropped");
+            }
+            *self = Coverage { rom: Some(hash.to_string()), ..Coverage::default() };
+        }
+    }
+
+    //Counts an instruction that executed, pc being its address and new_pc where the machine went
+    pub fn record(&mut self, pc: u16, opcode: u16, new_pc: u16) {
+        *self.executed.entry(pc).or_insert(0) += 1;
+        if is_skip(opcode) {
+            let branch = self.branches.entry(pc).or_insert((0, 0));
+            if new_pc == pc.wrapping_add(4) {
+                branch.0 += 1;
+            } else {
+                branch.1 += 1;
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: &Coverage) -> Result<(), String> {
+        match (&self.rom, &other.rom) {
+            (Some(a), Some(b)) if a != b => return Err("the coverage data is of another rom".to_string()),
+            (None, rom) => self.rom = rom.clone(),
+            _ => {}
+        }
+        for (addr, count) in &other.executed {
+            *self.executed.entry(*addr).or_insert(0) += count;
+        }
+        for (addr, (taken, not_taken)) in &other.branches {
+            let branch = self.branches.entry(*addr).or_insert((0, 0));
+            branch.0 += taken;
+            branch.1 += not_taken;
+        }
+        Ok(())
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        if let Some(rom) = &self.rom {
+            let _ = writeln!(out, "rom {}", rom);
+        }
+        for (addr, count) in &self.executed {
+            let _ = writeln!(out, "exec {:#05x} {}", addr, count);
+        }
+        for (addr, (taken, not_taken)) in &self.branches {
+            let _ = writeln!(out, "branch {:#05x} {} {}", addr, taken, not_taken);
+        }
+        out
+    }
+
+    //Saves the counts to path, adding them to the data a former run left there
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let mut total = if Path::new(path).exists() { load(path)? } else { Coverage::default() };
+        total.merge(self).map_err(|e| format!("{}: {}", path, e))?;
+        fs::write(path, total.to_text()).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    fn branch_text(&self, addr: u16) -> Option<String> {
+        self.branches.get(&addr).map(|(taken, not_taken)| format!("skipped {}, not skipped {}", taken, not_taken))
+    }
+
+    //The disassembly of the rom with the execution counts, "#####" marks what never executed
+    pub fn annotate(&self, data: &[u8], symbols: &Symbols) -> String {
+        let mut out = String::new();
+        let mut words = 0;
+        let mut covered = 0;
+        for offset in (0..data.len()).step_by(2) {
+            let addr = 0x200 + offset as u16;
+            for at in addr..addr + 2 {
+                if let Some(label) = symbols.label(at) {
+                    let _ = writeln!(out, "{:>10}  {}:", "", label);
+                }
+            }
+            let count = match self.executed.get(&addr) {
+                Some(count) => {
+                    covered += 1;
+                    count.to_string()
+                }
+                None => "#####".to_string(),
+            };
+            words += 1;
+            let opcode = disasm::fetch(data, offset);
+            let mut line = format!("{:>10}  {:#05x}  {:04x}  {}", count, addr, opcode, disasm::disassemble_symbols(opcode, symbols));
+            if let Some(branch) = self.branch_text(addr) {
+                line = format!("{:50} ; {}", line, branch);
+            }
+            let _ = writeln!(out, "{}", line);
+        }
+        let (directions, branches) = self.branch_summary();
+        let _ = writeln!(out, "\n{} of {} words executed, {} of {} skip directions taken", covered, words, directions, branches);
+        let _ = writeln!(out, "(words that are data or unreachable count as not executed)");
+        out
+    }
+
+    //Skip directions that happened, out of two per skip instruction that executed
+    fn branch_summary(&self) -> (usize, usize) {
+        let taken = self.branches.values().map(|(t, n)| (*t > 0) as usize + (*n > 0) as usize).sum();
+        (taken, self.branches.len() * 2)
+    }
+
+    //An lcov tracefile of the source lines, for genhtml and editor plugins
+    pub fn lcov(&self, symbols: &Symbols) -> Result<String, String> {
+        let source = symbols.source().ok_or("The symbols have no source lines")?;
+        //Data statements can't execute, they are left out when the source can be read
+        let text = fs::read_to_string(source).unwrap_or_default();
+        let source_lines: Vec<&str> = text.lines().collect();
+        let is_data = |line: usize| source_lines.get(line - 1).is_some_and(|text| {
+            let statement = text.split(';').next().unwrap_or("");
+            let statement = statement.rsplit(':').next().unwrap_or("").trim().to_uppercase();
+            statement.starts_with("DB ") || statement.starts_with("DW ")
+        });
+
+        let mut lines: BTreeMap<usize, (u64, Option<(u64, u64)>)> = BTreeMap::new();
+        for (addr, line) in symbols.lines() {
+            if is_data(line) {
+                continue;
+            }
+            let entry = lines.entry(line).or_insert((0, None));
+            entry.0 += self.executed.get(&addr).copied().unwrap_or(0);
+            if let Some(branch) = self.branches.get(&addr) {
+                entry.1 = Some(*branch);
+            }
+        }
+
+        let mut out = String::new();
+        let _ = writeln!(out, "TN:");
+        let _ = writeln!(out, "SF:{}", source.canonicalize().unwrap_or_else(|_| source.to_path_buf()).display());
+        let mut branch_count = 0;
+        let mut branches_hit = 0;
+        for (line, (_, branch)) in &lines {
+            if let Some((taken, not_taken)) = branch {
+                //An instruction that never executed has its branches marked with -
+                let executed = taken + not_taken > 0;
+                for (n, count) in [taken, not_taken].iter().enumerate() {
+                    let hits = if executed { count.to_string() } else { "-".to_string() };
+                    let _ = writeln!(out, "BRDA:{},0,{},{}", line, n, hits);
+                    branch_count += 1;
+                    branches_hit += (**count > 0) as usize;
+                }
+            }
+        }
+        for (line, (count, _)) in &lines {
+            let _ = writeln!(out, "DA:{},{}", line, count);
+        }
+        let _ = writeln!(out, "BRF:{}", branch_count);
+        let _ = writeln!(out, "BRH:{}", branches_hit);
+        let _ = writeln!(out, "LF:{}", lines.len());
+        let _ = writeln!(out, "LH:{}", lines.values().filter(|(count, _)| *count > 0).count());
+        let _ = writeln!(out, "end_of_record");
+        Ok(out)
+    }
+}
+
+pub fn load(path: &str) -> Result<Coverage, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut coverage = Coverage::default();
+    for (n, line) in text.lines().enumerate() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let addr = || words.get(1).and_then(|w| u16::from_str_radix(w.trim_start_matches("0x"), 16).ok());
+        let count = |i: usize| words.get(i).and_then(|w| w.parse::<u64>().ok());
+        let parsed = match words.first() {
+            None => Some(()),
+            Some(&"rom") => words.get(1).map(|hash| coverage.rom = Some(hash.to_string())),
+            Some(&"exec") => addr().zip(count(2)).map(|(addr, count)| {
+                *coverage.executed.entry(addr).or_insert(0) += count;
+            }),
+            Some(&"branch") => addr().zip(count(2)).zip(count(3)).map(|((addr, taken), not_taken)| {
+                let branch = coverage.branches.entry(addr).or_insert((0, 0));
+                branch.0 += taken;
+                branch.1 += not_taken;
+            }),
+            Some(_) => None,
+        };
+        if parsed.is_none() {
+            return Err(format!("{}: line {}: invalid coverage data", path, n + 1));
+        }
+    }
+    Ok(coverage)
+}
@@ -0,0 +1,625 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use serde_json::{json, Value};
+
+use crate::asm::{self, parse_number};
+use crate::breakpoints::{Breakpoints, Target};
+use crate::chip8::Chip8;
+use crate::disasm;
+use crate::expr;
+use crate::platform::Platform;
+use crate::symbols;
+
+//The machine has a single thread of execution
+const THREAD_ID: u64 = 1;
+const REGISTERS_REF: u64 = 1;
+const STACK_REF: u64 = 2;
+const SOURCE_EXTENSIONS: [&str; 2] = ["asm", "s"];
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    let canonical = |p: &Path| fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+    canonical(a) == canonical(b)
+}
+
+//Debug Adapter Protocol server, lets editors like VS Code drive the emulator. Requests are read on a
+//separate thread like the commands of the terminal debugger, so the window keeps rendering.
+pub struct Dap {
+    requests: Receiver<Value>,
+    output: Box<dyn Write + Send>,
+    seq: u64,
+    events: Vec<Value>,
+    //sent after the response of the request that caused them
+    breakpoints: Breakpoints,
+    source_ids: HashMap<String, Vec<u32>>,
+    //setBreakpoints replaces all the breakpoints of a file
+    instruction_ids: Vec<u32>,
+    function_ids: Vec<u32>,
+    launched: Option<PathBuf>,
+    stop_on_entry: bool,
+    paused: bool,
+    resume: bool,
+    until_sp: Option<u8>,
+    //stepping over a call or out of a subroutine stops when the stack is back at this depth
+    detached: bool,
+}
+
+impl Dap {
+    //Handles the pending requests and runs a frame unless the machine is paused; returns whether a frame ran
+    pub fn update(&mut self, platform: &mut Platform) -> bool {
+        self.handle_requests(platform);
+        self.run(platform)
+    }
+
+    pub fn handle_requests(&mut self, platform: &mut Platform) {
+        loop {
+            match self.requests.try_recv() {
+                Ok(request) => self.handle(&request, platform),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    if !self.detached {
+                        self.detach();
+                    }
+                    break;
+                }
+            }
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    //Rom started by a launch request since the last call
+    pub fn take_launched(&mut self) -> Option<PathBuf> {
+        self.launched.take()
+    }
+
+    pub fn run(&mut self, platform: &mut Platform) -> bool {
+        if self.paused {
+            return false;
+        }
+
+        let breakpoints = &self.breakpoints;
+        let until_sp = self.until_sp;
+        let mut resume = self.resume;
+        let mut logs = Vec::new();
+        let mut reason = None;
+        let interrupted = platform.run_frame(|chip| {
+            let first = resume;
+            resume = false;
+            let hit = breakpoints.check(chip, |message| logs.push(message));
+            if first {
+                return false;
+            }
+            if let Some(b) = hit {
+                reason = Some(("breakpoint", b.to_string()));
+            } else if until_sp.is_some_and(|sp| chip.sp <= sp) {
+                reason = Some(("step", String::new()));
+            }
+            reason.is_some()
+        });
+        self.resume = resume;
+
+        for message in logs {
+            self.event("output", json!({ "category": "console", "output": message + "\n" }));
+        }
+        if interrupted {
+            let (reason, description) = match platform.fault() {
+                Some(fault) => ("exception", fault.to_string()),
+                None => reason.unwrap_or(("pause", String::new())),
+            };
+            self.stopped(reason, &description);
+        }
+        self.flush();
+        true
+    }
+
+    fn handle(&mut self, request: &Value, platform: &mut Platform) {
+        let command = request["command"].as_str().unwrap_or("");
+        let args = &request["arguments"];
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsLogPoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsReadMemoryRequest": true,
+                "supportsDisassembleRequest": true,
+                "supportsEvaluateForHovers": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => self.launch(args, platform),
+            "setBreakpoints" => self.set_breakpoints(args, platform),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(args, platform.chip()),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(args, platform),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    self.stopped("entry", "");
+                } else {
+                    self.paused = false;
+                }
+                Ok(json!({}))
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => Ok(stack_trace(platform)),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS_REF, "expensive": false },
+                { "name": "Stack", "variablesReference": STACK_REF, "expensive": false },
+            ] })),
+            "variables" => Ok(variables(args["variablesReference"].as_u64().unwrap_or(0), platform.chip())),
+            "continue" => {
+                self.paused = false;
+                self.resume = true;
+                self.until_sp = None;
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" => self.step_over(platform),
+            "stepIn" => self.step_in(platform),
+            "stepOut" => self.step_out(platform),
+            "pause" => {
+                if !self.paused {
+                    self.stopped("pause", "");
+                }
+                Ok(json!({}))
+            }
+            "readMemory" => read_memory(args, platform.chip()),
+            "disassemble" => disassemble(args, platform),
+            "evaluate" => args["expression"].as_str().ok_or("Missing expression".to_string())
+                .and_then(expr::parse)
+                .map(|e| {
+                    let value = e.eval(platform.chip());
+                    json!({ "result": format!("{} ({:#x})", value, value), "variablesReference": 0 })
+                }),
+            "disconnect" => {
+                self.detach();
+                Ok(json!({}))
+            }
+            "terminate" => {
+                self.detach();
+                self.event("terminated", json!({}));
+                Ok(json!({}))
+            }
+            _ => Err(format!("Unsupported request {}", command)),
+        };
+        self.respond(request, result);
+        self.flush();
+    }
+
+    fn launch(&mut self, args: &Value, platform: &mut Platform) -> Result<Value, String> {
+        let program = args["program"].as_str().ok_or("The launch configuration needs a program")?;
+        let is_source = |p: &Path| p.extension().is_some_and(|e| SOURCE_EXTENSIONS.contains(&e.to_string_lossy().as_ref()));
+
+        //An assembly source is assembled on the fly, a rom can name the source it was built from
+        let source = match args["source"].as_str() {
+            Some(source) => Some(PathBuf::from(source)),
+            None if is_source(Path::new(program)) => Some(PathBuf::from(program)),
+            None => None,
+        };
+        let mut assembled = None;
+        if let Some(path) = &source {
+            let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            assembled = Some(asm::assemble(&text).map_err(|e| format!("{}: {}", path.display(), e))?);
+        }
+        let data = match &assembled {
+            Some(assembled) if is_source(Path::new(program)) => assembled.bytes.clone(),
+            _ => fs::read(program).map_err(|e| format!("{}: {}", program, e))?,
+        };
+
+        platform.open_rom_data(program, data);
+        if let (Some(assembled), Some(path)) = (&assembled, &source) {
+            platform.set_symbols(symbols::from_program(assembled, path));
+        }
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        self.paused = true;
+        self.launched = Some(PathBuf::from(program));
+        self.event("initialized", json!({}));
+        Ok(json!({}))
+    }
+
+    fn add_breakpoint(&mut self, addr: u16, args: &Value, chip: &Chip8) -> Result<u32, String> {
+        let condition = match args["condition"].as_str().filter(|c| !c.trim().is_empty()) {
+            Some(text) => Some((text.to_string(), expr::parse(text)?)),
+            None => None,
+        };
+        let log = args["logMessage"].as_str().map(|m| m.to_string());
+        if let Some(text) = &log {
+            expr::format(text, chip)?;
+        }
+        Ok(self.breakpoints.add(Target::Addr(addr), condition, log).id)
+    }
+
+    fn set_breakpoints(&mut self, args: &Value, platform: &Platform) -> Result<Value, String> {
+        let symbols = platform.symbols();
+        let path = args["source"]["path"].as_str().unwrap_or("").to_string();
+        for id in self.source_ids.remove(&path).unwrap_or_default() {
+            self.breakpoints.remove(id);
+        }
+
+        let mut ids = Vec::new();
+        let mut results = Vec::new();
+        for requested in args["breakpoints"].as_array().cloned().unwrap_or_default() {
+            let line = requested["line"].as_u64().unwrap_or(0) as usize;
+            let location = symbols.source().filter(|source| same_file(source, Path::new(&path))).and_then(|_| symbols.line_addr(line));
+            let result = match location {
+                Some((line, addr)) => match self.add_breakpoint(addr, &requested, platform.chip()) {
+                    Ok(id) => {
+                        ids.push(id);
+                        json!({ "id": id, "verified": true, "line": line, "instructionReference": format!("{:#05x}", addr) })
+                    }
+                    Err(e) => json!({ "verified": false, "line": line, "message": e }),
+                },
+                None => json!({ "verified": false, "line": line, "message": "No instruction at this line" }),
+            };
+            results.push(result);
+        }
+        self.source_ids.insert(path, ids);
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value, chip: &Chip8) -> Result<Value, String> {
+        for id in std::mem::take(&mut self.instruction_ids) {
+            self.breakpoints.remove(id);
+        }
+
+        let mut results = Vec::new();
+        for requested in args["breakpoints"].as_array().cloned().unwrap_or_default() {
+            let reference = requested["instructionReference"].as_str().and_then(parse_number);
+            let offset = requested["offset"].as_i64().unwrap_or(0);
+            let result = match reference.map(|r| r as i64 + offset) {
+                Some(addr) if (0..0x1000).contains(&addr) => match self.add_breakpoint(addr as u16, &requested, chip) {
+                    Ok(id) => {
+                        self.instruction_ids.push(id);
+                        json!({ "id": id, "verified": true, "instructionReference": format!("{:#05x}", addr) })
+                    }
+                    Err(e) => json!({ "verified": false, "message": e }),
+                },
+                _ => json!({ "verified": false, "message": "Invalid address" }),
+            };
+            results.push(result);
+        }
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    //Function breakpoints are labels
+    fn set_function_breakpoints(&mut self, args: &Value, platform: &Platform) -> Result<Value, String> {
+        for id in std::mem::take(&mut self.function_ids) {
+            self.breakpoints.remove(id);
+        }
+
+        let mut results = Vec::new();
+        for requested in args["breakpoints"].as_array().cloned().unwrap_or_default() {
+            let name = requested["name"].as_str().unwrap_or("");
+            let result = match platform.symbols().resolve(name) {
+                Some(addr) => match self.add_breakpoint(addr, &requested, platform.chip()) {
+                    Ok(id) => {
+                        self.function_ids.push(id);
+                        json!({ "id": id, "verified": true, "instructionReference": format!("{:#05x}", addr) })
+                    }
+                    Err(e) => json!({ "verified": false, "message": e }),
+                },
+                None => json!({ "verified": false, "message": format!("Unknown label {}", name) }),
+            };
+            results.push(result);
+        }
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    fn step_in(&mut self, platform: &mut Platform) -> Result<Value, String> {
+        platform.step();
+        match platform.fault() {
+            Some(fault) => self.stopped("exception", &fault.to_string()),
+            None => self.stopped("step", ""),
+        }
+        Ok(json!({}))
+    }
+
+    //Runs a CALL until it returns, anything else is a single step
+    fn step_over(&mut self, platform: &mut Platform) -> Result<Value, String> {
+        let chip = platform.chip();
+        if disasm::fetch(&chip.mem, chip.pc as usize) >> 12 != 0x2 {
+            return self.step_in(platform);
+        }
+        self.run_until(chip.sp);
+        Ok(json!({}))
+    }
+
+    fn step_out(&mut self, platform: &mut Platform) -> Result<Value, String> {
+        match platform.chip().sp {
+            0 => self.step_in(platform),
+            sp => {
+                self.run_until(sp - 1);
+                Ok(json!({}))
+            }
+        }
+    }
+
+    fn run_until(&mut self, sp: u8) {
+        self.until_sp = Some(sp);
+        self.paused = false;
+        self.resume = true;
+    }
+
+    fn stopped(&mut self, reason: &str, description: &str) {
+        self.paused = true;
+        self.until_sp = None;
+        self.event("stopped", json!({
+            "reason": reason,
+            "description": description,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        }));
+    }
+
+    fn detach(&mut self) {
+        self.detached = true;
+        self.paused = false;
+        self.until_sp = None;
+        self.breakpoints.clear();
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response);
+    }
+
+    fn event(&mut self, name: &str, body: Value) {
+        self.events.push(json!({ "type": "event", "event": name, "body": body }));
+    }
+
+    fn flush(&mut self) {
+        for event in std::mem::take(&mut self.events) {
+            self.send(event);
+        }
+    }
+
+    fn send(&mut self, mut message: Value) {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = message.to_string();
+        let written = write!(self.output, "Content-Length: {}\r\n\r\n{}", body.len(), body).and_then(|_| self.output.flush());
+        if written.is_err() && !self.detached {
+            self.detach();
+        }
+    }
+}
+
+fn frame(id: usize, addr: u16, platform: &Platform) -> Value {
+    let symbols = platform.symbols();
+    let opcode = disasm::fetch(&platform.chip().mem, addr as usize);
+    let name = match symbols.offset_label(addr) {
+        Some(label) => format!("{}  {}", label, disasm::disassemble_symbols(opcode, symbols)),
+        None => format!("{:#05x}  {}", addr, disasm::disassemble(opcode)),
+    };
+    let mut frame = json!({
+        "id": id,
+        "name": name,
+        "line": 0,
+        "column": 0,
+        "instructionPointerReference": format!("{:#05x}", addr),
+    });
+    if let (Some(source), Some(line)) = (symbols.source(), symbols.line(addr)) {
+        frame["source"] = json!({ "path": source.display().to_string() });
+        frame["line"] = json!(line);
+        frame["column"] = json!(1);
+    }
+    frame
+}
+
+//The current instruction followed by the CALL of every return address on the stack
+fn stack_trace(platform: &Platform) -> Value {
+    let chip = platform.chip();
+    let mut frames = vec![frame(0, chip.pc, platform)];
+    for (n, k) in (0..chip.sp as usize).rev().enumerate() {
+        frames.push(frame(n + 1, chip.stack[k].wrapping_sub(2), platform));
+    }
+    json!({ "stackFrames": frames, "totalFrames": chip.sp as usize + 1 })
+}
+
+fn disassemble(args: &Value, platform: &Platform) -> Result<Value, String> {
+    let chip = platform.chip();
+    let symbols = platform.symbols();
+    let base = args["memoryReference"].as_str().and_then(parse_number).ok_or("Invalid memory reference")?;
+    let start = base as i64 + args["offset"].as_i64().unwrap_or(0) + 2 * args["instructionOffset"].as_i64().unwrap_or(0);
+    let count = args["instructionCount"].as_i64().unwrap_or(0);
+
+    let mut instructions = Vec::new();
+    for k in 0..count {
+        let addr = start + 2 * k;
+        if !(0..chip.mem.len() as i64 - 1).contains(&addr) {
+            instructions.push(json!({ "address": format!("{:#05x}", addr), "instruction": "??", "presentationHint": "invalid" }));
+            continue;
+        }
+        let opcode = disasm::fetch(&chip.mem, addr as usize);
+        let mut instruction = json!({
+            "address": format!("{:#05x}", addr),
+            "instructionBytes": format!("{:02x} {:02x}", opcode >> 8, opcode & 0xff),
+            "instruction": disasm::disassemble_symbols(opcode, symbols),
+        });
+        if let Some(label) = symbols.label(addr as u16) {
+            instruction["symbol"] = json!(label);
+        }
+        if let (Some(source), Some(line)) = (symbols.source(), symbols.line(addr as u16)) {
+            instruction["location"] = json!({ "path": source.display().to_string() });
+            instruction["line"] = json!(line);
+        }
+        instructions.push(instruction);
+    }
+    Ok(json!({ "instructions": instructions }))
+}
+
+fn variable(name: String, value: String) -> Value {
+    json!({ "name": name, "value": value, "variablesReference": 0 })
+}
+
+fn variables(reference: u64, chip: &Chip8) -> Value {
+    let mut list = Vec::new();
+    match reference {
+        REGISTERS_REF => {
+            for (n, value) in chip.reg.iter().enumerate() {
+                list.push(variable(format!("V{:X}", n), format!("{:#04x}", value)));
+            }
+            let mut i = variable("I".to_string(), format!("{:#05x}", chip.i));
+            i["memoryReference"] = json!(format!("{:#05x}", chip.i));
+            list.push(i);
+            let mut pc = variable("PC".to_string(), format!("{:#05x}", chip.pc));
+            pc["memoryReference"] = json!(format!("{:#05x}", chip.pc));
+            list.push(pc);
+            list.push(variable("SP".to_string(), chip.sp.to_string()));
+            list.push(variable("DT".to_string(), format!("{:#04x}", chip.delay)));
+            list.push(variable("ST".to_string(), format!("{:#04x}", chip.sound)));
+        }
+        STACK_REF => {
+            for k in (0..chip.sp as usize).rev() {
+                list.push(variable(format!("[{}]", k), format!("{:#05x}", chip.stack[k])));
+            }
+        }
+        _ => {}
+    }
+    json!({ "variables": list })
+}
+
+fn read_memory(args: &Value, chip: &Chip8) -> Result<Value, String> {
+    let base = args["memoryReference"].as_str().and_then(parse_number).ok_or("Invalid memory reference")?;
+    let start = (base as i64).saturating_add(args["offset"].as_i64().unwrap_or(0)).clamp(0, chip.mem.len() as i64) as usize;
+    let count = args["count"].as_u64().unwrap_or(0) as usize;
+    let end = start.saturating_add(count).min(chip.mem.len());
+    Ok(json!({
+        "address": format!("{:#05x}", start),
+        "data": base64(&chip.mem[start..end]),
+        "unreadableBytes": count - (end - start),
+    }))
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (k, b)| n | (*b as u32) << (16 - 8 * k));
+        for k in 0..4 {
+            if k <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * k) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+//Messages are JSON bodies after a Content-Length header
+fn read_messages<R: Read + Send + 'static>(input: R) -> Receiver<Value> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(input);
+        loop {
+            let mut length = None;
+            loop {
+                let mut header = String::new();
+                match reader.read_line(&mut header) {
+                    Ok(0) | Err(_) => return,
+                    Ok(_) => {}
+                }
+                let header = header.trim();
+                if header.is_empty() {
+                    break;
+                }
+                if let Some(value) = header.strip_prefix("Content-Length:") {
+                    length = value.trim().parse::<usize>().ok();
+                }
+            }
+            let mut body = vec![0; length.unwrap_or(0)];
+            if reader.read_exact(&mut body).is_err() {
+                return;
+            }
+            match serde_json::from_slice(&body) {
+                Ok(message) => {
+                    if tx.send(message).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => eprintln!("Invalid debug adapter message: {}", e),
+            }
+        }
+    });
+    rx
+}
+
+//The protocol owns stdout, everything the emulator prints goes to stderr instead
+#[cfg(unix)]
+fn protocol_stdout() -> Result<Box<dyn Write + Send>, String> {
+    use std::os::unix::io::FromRawFd;
+    let _ = io::stdout().flush();
+    unsafe {
+        let fd = libc::dup(1);
+        libc::dup2(2, 1);
+        Ok(Box::new(fs::File::from_raw_fd(fd)))
+    }
+}
+
+//Without dup2 the messages of the emulator would end up in the protocol
+#[cfg(not(unix))]
+fn protocol_stdout() -> Result<Box<dyn Write + Send>, String> {
+    Err("The debug adapter only serves stdio on Unix, use a port instead".to_string())
+}
+
+//Serves the protocol on stdio, or waits for a client on a local TCP port
+pub fn new_dap(endpoint: &str) -> Result<Dap, String> {
+    let (requests, output): (Receiver<Value>, Box<dyn Write + Send>) = if endpoint == "stdio" {
+        let output = protocol_stdout()?;
+        (read_messages(io::stdin()), output)
+    } else {
+        let port: u16 = endpoint.parse().map_err(|_| format!("Expected stdio or a port, got {}", endpoint))?;
+        let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| format!("Port {}: {}", port, e))?;
+        println!("Waiting for a debug adapter client on 127.0.0.1:{}", port);
+        let (stream, _) = listener.accept().map_err(|e| e.to_string())?;
+        let input = stream.try_clone().map_err(|e| e.to_string())?;
+        (read_messages(input), Box::new(stream))
+    };
+
+    Ok(Dap {
+        requests,
+        output,
+        seq: 0,
+        events: Vec::new(),
+        breakpoints: Breakpoints::default(),
+        source_ids: HashMap::new(),
+        instruction_ids: Vec::new(),
+        function_ids: Vec::new(),
+        launched: None,
+        stop_on_entry: false,
+        paused: true,
+        resume: false,
+        until_sp: None,
+        detached: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_memory_past_the_end() {
+        let chip = crate::chip8::new_chip8();
+        let result = read_memory(&json!({ "memoryReference": "0xffe", "count": u64::MAX }), &chip).unwrap();
+        assert_eq!(result["data"], "AAA=");
+        assert_eq!(result["unreadableBytes"], u64::MAX - 2);
+    }
+}
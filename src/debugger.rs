@@ -0,0 +1,384 @@
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use crate::asm::parse_number;
+use crate::breakpoints::{self, Breakpoints};
+use crate::disasm;
+use crate::expr;
+use crate::platform::Platform;
+
+const PROMPT: &str = "(rust_8) ";
+const HELP: &str = "\
+step [n]          execute n instructions (default 1)
+continue          run until a breakpoint is hit
+back [n]          go back n instructions (default 1)
+rcontinue         go back to the previous breakpoint hit
+break ADDR        set a breakpoint at an address or label, also break op DXYN or break if EXPR
+break ... if EXPR only stop when EXPR is not 0, e.g. break 0x2a4 if v3 > 10
+watch [read|write|access] ADDR [LEN]|REG [if EXPR]
+                  stop when memory or a register is accessed (default write)
+log SPEC \"TEXT\"   print TEXT instead of stopping, {EXPR} and {EXPR:x} are expanded
+breakpoints       list the breakpoints
+delete [ID]       delete a breakpoint, or all of them
+regs              print the registers
+stack             print the stack
+bt                print the call stack with labels and source lines
+mem ADDR LEN      dump memory
+disasm [ADDR] [N] disassemble N instructions at ADDR (default pc)
+set REG VALUE     set V0-VF, I, PC, DT or ST
+poke ADDR BYTE    write a byte to memory
+key N down|up     press or release hex key N
+pause             stop running
+help              print this help";
+
+//Terminal debugger: commands are read from stdin on a separate thread so that the window keeps
+//rendering the framebuffer while the machine is paused.
+pub struct Debugger {
+    commands: Receiver<String>,
+    breakpoints: Breakpoints,
+    paused: bool,
+    resume: bool,
+    //set when continuing, so the breakpoint at pc does not stop the machine again right away
+    detached: bool,
+    //the machine stopped on a breakpoint and the logpoints at pc were printed
+    logged: bool,
+}
+
+impl Debugger {
+    //Handles the pending commands and runs a frame unless the machine is paused; returns whether a frame ran
+    pub fn update(&mut self, platform: &mut Platform) -> bool {
+        loop {
+            match self.commands.try_recv() {
+                Ok(line) => {
+                    self.execute(&line, platform);
+                    if !self.paused {
+                        //The commands after a continue wait for the machine to stop again
+                        break;
+                    }
+                    prompt();
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.detached = true;
+                    break;
+                }
+            }
+        }
+
+        if self.paused {
+            return false;
+        }
+
+        let breakpoints = &self.breakpoints;
+        let mut resume = self.resume;
+        let logged = self.logged;
+        let mut reason = None;
+        let interrupted = platform.run_frame(|chip| {
+            //The logpoints at the breakpoint we stopped on have already printed
+            let quiet = resume && logged;
+            let hit = breakpoints.check(chip, |message| if !quiet { println!("[{:#05x}] {}", chip.pc, message) }).map(|b| format!("Breakpoint {} ({})", b.id, b));
+            if resume {
+                resume = false;
+                return false;
+            }
+            reason = hit;
+            reason.is_some()
+        });
+        self.resume = resume;
+        self.logged = reason.is_some();
+        if interrupted {
+            //The platform already reported the fault
+            match platform.fault() {
+                Some(_) => self.stopped(platform, "Halted"),
+                None => self.stopped(platform, reason.as_deref().unwrap_or("Breakpoint")),
+            }
+        }
+        true
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    //stdin was closed, no more commands will arrive
+    pub fn detached(&self) -> bool {
+        self.detached
+    }
+
+    fn stopped(&mut self, platform: &Platform, reason: &str) {
+        self.paused = true;
+        print_location(platform, reason);
+        prompt();
+    }
+
+    fn execute(&mut self, line: &str, platform: &mut Platform) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let result = match words.as_slice() {
+            [] => Ok(()),
+            ["step"] | ["s"] => self.step(platform, 1),
+            ["step", n] | ["s", n] => number(n).and_then(|n| self.step(platform, n)),
+            ["continue"] | ["c"] => {
+                self.paused = false;
+                self.resume = true;
+                Ok(())
+            }
+            ["back"] | ["bs"] => self.step_back(platform, 1),
+            ["back", n] | ["bs", n] => number(n).and_then(|n| self.step_back(platform, n)),
+            ["rcontinue"] | ["rc"] => self.reverse_continue(platform),
+            ["pause"] => {
+                self.paused = true;
+                Ok(())
+            }
+            ["break", ..] | ["b", ..] | ["watch", ..] | ["log", ..] => self.add_breakpoint(line.trim(), platform),
+            ["breakpoints"] | ["bl"] => {
+                for b in self.breakpoints.iter() {
+                    println!("{:3}  {}", b.id, b);
+                }
+                Ok(())
+            }
+            ["delete"] => {
+                self.breakpoints.clear();
+                Ok(())
+            }
+            ["delete", id] => number(id).and_then(|id| {
+                if self.breakpoints.remove(id as u32) { Ok(()) } else { Err(format!("No breakpoint {}", id)) }
+            }),
+            ["regs"] => {
+                platform.chip().print_registers();
+                Ok(())
+            }
+            ["stack"] => {
+                platform.chip().print_stack();
+                Ok(())
+            }
+            ["bt"] | ["backtrace"] => {
+                print_backtrace(platform);
+                Ok(())
+            }
+            ["mem", addr, len] => address(platform, addr).and_then(|addr| number(len).map(|len| dump(platform, addr, len))),
+            ["disasm"] => {
+                let pc = platform.chip().pc;
+                print_disasm(platform, pc, 10);
+                Ok(())
+            }
+            ["disasm", addr] => address(platform, addr).map(|addr| print_disasm(platform, addr, 10)),
+            ["disasm", addr, n] => address(platform, addr).and_then(|addr| number(n).map(|n| print_disasm(platform, addr, n))),
+            ["set", reg, value] => number(value).and_then(|value| set_register(platform, reg, value)),
+            ["poke", addr, byte] => address(platform, addr).and_then(|addr| number(byte).and_then(|byte| {
+                let chip = platform.chip_mut();
+                match chip.mem.get_mut(addr as usize) {
+                    Some(cell) if byte <= 0xff => {
+                        *cell = byte as u8;
+                        Ok(())
+                    }
+                    _ => Err("Address or byte out of range".to_string()),
+                }
+            })),
+            ["key", n, state] => number(n).and_then(|n| {
+                let pressed = match *state {
+                    "down" => true,
+                    "up" => false,
+                    _ => return Err("Expected down or up".to_string()),
+                };
+                if n > 0xf {
+                    return Err("Keys are 0 to F".to_string());
+                }
+                platform.set_key(n as u8, pressed);
+                Ok(())
+            }),
+            ["help"] | ["h"] => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            _ => Err(format!("Unknown command '{}', try help", line.trim())),
+        };
+        if let Err(e) = result {
+            println!("{}", e);
+        }
+    }
+
+    //break SPEC, watch ... and log SPEC "TEXT"
+    fn add_breakpoint(&mut self, line: &str, platform: &Platform) -> Result<(), String> {
+        let (command, rest) = line.split_at(line.find(' ').unwrap_or(line.len()));
+        let (spec, log) = match command {
+            "log" => {
+                let open = rest.find('"').ok_or("Expected log SPEC \"TEXT\"")?;
+                let text = rest[open + 1..].strip_suffix('"').ok_or("Unclosed \"")?;
+                (rest[..open].to_string(), Some(text.to_string()))
+            }
+            "watch" => (line.to_string(), None),
+            _ => (rest.to_string(), None),
+        };
+        let (target, condition) = breakpoints::parse_spec(&spec, platform.symbols())?;
+        if let Some(text) = &log {
+            expr::format(text, platform.chip())?;
+        }
+        let b = self.breakpoints.add(target, condition, log);
+        println!("Breakpoint {}: {}", b.id, b);
+        Ok(())
+    }
+
+    fn step(&mut self, platform: &mut Platform, n: u16) -> Result<(), String> {
+        self.paused = true;
+        self.logged = false;
+        for _ in 0..n {
+            if platform.fault().is_some() {
+                break;
+            }
+            platform.step();
+        }
+        match platform.fault() {
+            Some(_) => print_location(platform, "Halted"),
+            None => print_location(platform, "Stepped"),
+        }
+        Ok(())
+    }
+
+    fn step_back(&mut self, platform: &mut Platform, n: u16) -> Result<(), String> {
+        self.paused = true;
+        self.logged = false;
+        let start = platform.history_start().ok_or("Nothing has been executed yet")?;
+        let target = platform.cycles().saturating_sub(n as u64);
+        if target < start {
+            return Err(format!("The history only goes back {} instructions", platform.cycles() - start));
+        }
+        platform.replay(target, target, |_, _| {})?;
+        print_location(platform, "Stepped back");
+        Ok(())
+    }
+
+    //Replays the recorded history to find the last breakpoint hit before the current instruction
+    fn reverse_continue(&mut self, platform: &mut Platform) -> Result<(), String> {
+        self.paused = true;
+        self.logged = false;
+        let start = platform.history_start().ok_or("Nothing has been executed yet")?;
+        let now = platform.cycles();
+        let breakpoints = &self.breakpoints;
+        let mut hit = None;
+        platform.replay(start, now, |chip, cycles| {
+            if let Some(b) = breakpoints.check(chip, |_| {}) {
+                hit = Some((cycles, format!("Breakpoint {} ({})", b.id, b)));
+            }
+        })?;
+        match hit {
+            Some((cycles, reason)) => {
+                platform.replay(cycles, cycles, |_, _| {})?;
+                print_location(platform, &reason);
+            }
+            None => {
+                platform.replay(start, start, |_, _| {})?;
+                print_location(platform, "Start of the history");
+            }
+        }
+        Ok(())
+    }
+}
+
+//"0x204 (loop+2, game.asm:6)" when there are symbols
+fn location(platform: &Platform, addr: u16) -> String {
+    match platform.symbols().describe(addr) {
+        Some(symbol) => format!("{:#05x} ({})", addr, symbol),
+        None => format!("{:#05x}", addr),
+    }
+}
+
+fn print_location(platform: &Platform, reason: &str) {
+    let chip = platform.chip();
+    let opcode = disasm::fetch(&chip.mem, chip.pc as usize);
+    println!("{} at {}: {}", reason, location(platform, chip.pc), disasm::disassemble_symbols(opcode, platform.symbols()));
+}
+
+//The current instruction, then the CALL of every return address on the stack
+fn print_backtrace(platform: &Platform) {
+    let chip = platform.chip();
+    let mut frames = vec![chip.pc];
+    frames.extend(chip.stack[..chip.sp as usize].iter().rev().map(|ret| ret.wrapping_sub(2)));
+    for (n, addr) in frames.into_iter().enumerate() {
+        let opcode = disasm::fetch(&chip.mem, addr as usize);
+        println!("#{:<2} {}  {}", n, location(platform, addr), disasm::disassemble_symbols(opcode, platform.symbols()));
+    }
+}
+
+fn prompt() {
+    print!("{}", PROMPT);
+    let _ = io::stdout().flush();
+}
+
+fn number(text: &str) -> Result<u16, String> {
+    parse_number(text).ok_or(format!("Invalid number {}", text))
+}
+
+fn address(platform: &Platform, text: &str) -> Result<u16, String> {
+    platform.symbols().resolve(text).ok_or(format!("Invalid address or unknown label {}", text))
+}
+
+fn dump(platform: &Platform, addr: u16, len: u16) {
+    let mem = &platform.chip().mem;
+    let end = (addr as usize + len as usize).min(mem.len());
+    for start in (addr as usize..end).step_by(16) {
+        let bytes: Vec<String> = mem[start..end.min(start + 16)].iter().map(|b| format!("{:02x}", b)).collect();
+        println!("{:#05x}  {}", start, bytes.join(" "));
+    }
+}
+
+fn print_disasm(platform: &Platform, addr: u16, n: u16) {
+    let chip = platform.chip();
+    for k in 0..n {
+        let at = addr as usize + 2 * k as usize;
+        if at >= chip.mem.len() {
+            break;
+        }
+        if let Some(label) = platform.symbols().label(at as u16) {
+            println!("   {}:", label);
+        }
+        let marker = if at == chip.pc as usize { "->" } else { "  " };
+        let opcode = disasm::fetch(&chip.mem, at);
+        println!("{} {:#05x}  {:04x}  {}", marker, at, opcode, disasm::disassemble_symbols(opcode, platform.symbols()));
+    }
+}
+
+fn set_register(platform: &mut Platform, reg: &str, value: u16) -> Result<(), String> {
+    let chip = platform.chip_mut();
+    let upper = reg.to_uppercase();
+    let byte = || if value <= 0xff { Ok(value as u8) } else { Err(format!("{:#x} does not fit into {}", value, reg)) };
+    match upper.as_str() {
+        "I" => chip.i = value,
+        "PC" => chip.pc = value,
+        "DT" => chip.delay = byte()?,
+        "ST" => chip.sound = byte()?,
+        _ if upper.len() == 2 && upper.starts_with('V') => {
+            let index = u8::from_str_radix(&upper[1..], 16).map_err(|_| format!("Unknown register {}", reg))?;
+            chip.reg[index as usize] = byte()?;
+        }
+        _ => return Err(format!("Unknown register {}", reg)),
+    }
+    Ok(())
+}
+
+pub fn new_debugger() -> Debugger {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            match line {
+                Ok(line) => {
+                    if tx.send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+
+    println!("Debugger started, the machine is paused. Type help for the commands.");
+    prompt();
+    Debugger {
+        commands: rx,
+        breakpoints: Breakpoints::default(),
+        paused: true,
+        resume: false,
+        detached: false,
+        logged: false,
+    }
+}
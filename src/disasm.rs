@@ -0,0 +1,104 @@
+use crate::symbols::Symbols;
+
+//Disassembler using the mnemonics of Cowgod's Chip-8 technical reference, the same the assembler reads
+
+pub fn disassemble(opcode: u16) -> String {
+    let x = (opcode & 0xf00) >> 8;
+    let y = (opcode & 0xf0) >> 4;
+    let n = opcode & 0xf;
+    let kk = opcode & 0xff;
+    let nnn = opcode & 0xfff;
+
+    match opcode >> 12 {
+        0x0 => match opcode {
+            0x00e0 => "CLS".to_string(),
+            0x00ee => "RET".to_string(),
+            _ => format!("SYS {:#05x}", nnn),
+        },
+        0x1 => format!("JP {:#05x}", nnn),
+        0x2 => format!("CALL {:#05x}", nnn),
+        0x3 => format!("SE V{:X}, {:#04x}", x, kk),
+        0x4 => format!("SNE V{:X}, {:#04x}", x, kk),
+        0x5 if n == 0 => format!("SE V{:X}, V{:X}", x, y),
+        0x6 => format!("LD V{:X}, {:#04x}", x, kk),
+        0x7 => format!("ADD V{:X}, {:#04x}", x, kk),
+        0x8 => match n {
+            0x0 => format!("LD V{:X}, V{:X}", x, y),
+            0x1 => format!("OR V{:X}, V{:X}", x, y),
+            0x2 => format!("AND V{:X}, V{:X}", x, y),
+            0x3 => format!("XOR V{:X}, V{:X}", x, y),
+            0x4 => format!("ADD V{:X}, V{:X}", x, y),
+            0x5 => format!("SUB V{:X}, V{:X}", x, y),
+            0x6 => format!("SHR V{:X}, V{:X}", x, y),
+            0x7 => format!("SUBN V{:X}, V{:X}", x, y),
+            0xe => format!("SHL V{:X}, V{:X}", x, y),
+            _ => data(opcode),
+        },
+        0x9 if n == 0 => format!("SNE V{:X}, V{:X}", x, y),
+        0xa => format!("LD I, {:#05x}", nnn),
+        0xb => format!("JP V0, {:#05x}", nnn),
+        0xc => format!("RND V{:X}, {:#04x}", x, kk),
+        0xd => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xe => match kk {
+            0x9e => format!("SKP V{:X}", x),
+            0xa1 => format!("SKNP V{:X}", x),
+            _ => data(opcode),
+        },
+        0xf => match kk {
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0a => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1e => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            _ => data(opcode),
+        },
+        _ => data(opcode),
+    }
+}
+
+//Like disassemble, with the label in place of an address operand
+pub fn disassemble_symbols(opcode: u16, symbols: &Symbols) -> String {
+    let text = disassemble(opcode);
+    let nnn = opcode & 0xfff;
+    match (opcode >> 12, symbols.label(nnn)) {
+        (0x1 | 0x2 | 0xa | 0xb, Some(label)) => text.replace(&format!("{:#05x}", nnn), label),
+        _ => text,
+    }
+}
+
+//Words that are no instruction are shown as data
+fn data(opcode: u16) -> String {
+    format!("DW {:#06x}", opcode)
+}
+
+//Reads the big endian instruction at addr, bytes outside of mem read as 0
+pub fn fetch(mem: &[u8], addr: usize) -> u16 {
+    let hi = mem.get(addr).copied().unwrap_or(0) as u16;
+    let lo = mem.get(addr + 1).copied().unwrap_or(0) as u16;
+    (hi << 8) | lo
+}
+
+//One line per instruction: address, opcode and mnemonic, labels get a line of their own
+pub fn listing(data: &[u8], start: u16, symbols: &Symbols) -> Vec<String> {
+    let mut lines = Vec::new();
+    for offset in (0..data.len()).step_by(2) {
+        let addr = start as usize + offset;
+        //Labels at odd addresses are missed by the 2 byte steps, show them anyway
+        for at in addr..addr + 2 {
+            if let Some(label) = symbols.label(at as u16) {
+                lines.push(format!("{}:", label));
+            }
+        }
+        let opcode = fetch(data, offset);
+        let mut line = format!("{:#05x}  {:04x}  {}", addr, opcode, disassemble_symbols(opcode, symbols));
+        if let Some(source_line) = symbols.line(addr as u16) {
+            line = format!("{:40} ; line {}", line, source_line);
+        }
+        lines.push(line);
+    }
+    lines
+}
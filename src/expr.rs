@@ -0,0 +1,256 @@
+use crate::chip8::Chip8;
+use crate::disasm;
+
+//Expression language of the conditional breakpoints and logpoints, e.g. "pc == 0x2a4 && v3 > 10".
+//Values are the registers v0-vf, i, pc, sp, dt, st, the opcode at pc (op), mem[ADDR] and key[N],
+//combined with the C operators. Comparisons and logical operators evaluate to 0 or 1.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Var {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+    Opcode,
+}
+
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Number(i64),
+    Var(Var),
+    Mem(Box<Expr>),
+    Key(Box<Expr>),
+    Unary(char, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+//Binary operators from the lowest to the highest precedence
+const LEVELS: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<=", ">=", "<", ">"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+impl Expr {
+    pub fn eval(&self, chip: &Chip8) -> i64 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Var(var) => match var {
+                Var::V(n) => chip.reg[*n as usize] as i64,
+                Var::I => chip.i as i64,
+                Var::Pc => chip.pc as i64,
+                Var::Sp => chip.sp as i64,
+                Var::Dt => chip.delay as i64,
+                Var::St => chip.sound as i64,
+                Var::Opcode => disasm::fetch(&chip.mem, chip.pc as usize) as i64,
+            },
+            Expr::Mem(addr) => chip.mem.get(addr.eval(chip) as usize).copied().unwrap_or(0) as i64,
+            Expr::Key(n) => chip.keypad.get(n.eval(chip) as usize).copied().unwrap_or(0) as i64,
+            Expr::Unary(op, e) => {
+                let v = e.eval(chip);
+                match op {
+                    '-' => v.wrapping_neg(),
+                    '!' => (v == 0) as i64,
+                    _ => !v,
+                }
+            }
+            Expr::Binary(op, l, r) => {
+                let a = l.eval(chip);
+                //Short circuit so that "sp > 0 && mem[...]" only looks at memory when needed
+                match *op {
+                    "&&" => return (a != 0 && r.eval(chip) != 0) as i64,
+                    "||" => return (a != 0 || r.eval(chip) != 0) as i64,
+                    _ => {}
+                }
+                let b = r.eval(chip);
+                match *op {
+                    "|" => a | b,
+                    "^" => a ^ b,
+                    "&" => a & b,
+                    "==" => (a == b) as i64,
+                    "!=" => (a != b) as i64,
+                    "<=" => (a <= b) as i64,
+                    ">=" => (a >= b) as i64,
+                    "<" => (a < b) as i64,
+                    ">" => (a > b) as i64,
+                    "<<" => a.wrapping_shl(b as u32),
+                    ">>" => a.wrapping_shr(b as u32),
+                    "+" => a.wrapping_add(b),
+                    "-" => a.wrapping_sub(b),
+                    "*" => a.wrapping_mul(b),
+                    "/" => if b == 0 { 0 } else { a.wrapping_div(b) },
+                    _ => if b == 0 { 0 } else { a.wrapping_rem(b) },
+                }
+            }
+        }
+    }
+
+    pub fn is_true(&self, chip: &Chip8) -> bool {
+        self.eval(chip) != 0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+const OPERATORS: [&str; 24] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>",
+    "|", "^", "&", "<", ">", "+", "-", "*", "/", "%", "!", "~", "(", ")", "[", "]",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let c = chars[pos];
+        if c.is_whitespace() {
+            pos += 1;
+        } else if c.is_alphanumeric() || c == '_' || c == '#' || c == '$' {
+            let start = pos;
+            while pos < chars.len() && (chars[pos].is_alphanumeric() || chars[pos] == '_' || chars[pos] == '#' || chars[pos] == '$') {
+                pos += 1;
+            }
+            let word: String = chars[start..pos].iter().collect();
+            if c.is_ascii_digit() || c == '#' || c == '$' {
+                let n = crate::asm::parse_number(&word).ok_or(format!("invalid number {}", word))?;
+                tokens.push(Token::Number(n as i64));
+            } else {
+                tokens.push(Token::Ident(word.to_lowercase()));
+            }
+        } else {
+            let rest: String = chars[pos..].iter().take(2).collect();
+            let op = OPERATORS.iter().find(|op| rest.starts_with(*op)).ok_or(format!("unexpected '{}'", c))?;
+            tokens.push(Token::Op(op));
+            pos += op.len();
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Op(o)) if o == op => Ok(()),
+            _ => Err(format!("expected '{}'", op)),
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            if !LEVELS[level].contains(&op) {
+                break;
+            }
+            self.pos += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Op("-")) => Ok(Expr::Unary('-', Box::new(self.unary()?))),
+            Some(Token::Op("!")) => Ok(Expr::Unary('!', Box::new(self.unary()?))),
+            Some(Token::Op("~")) => Ok(Expr::Unary('~', Box::new(self.unary()?))),
+            Some(Token::Op("(")) => {
+                let e = self.binary(0)?;
+                self.expect(")")?;
+                Ok(e)
+            }
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Ident(name)) => self.ident(&name),
+            Some(Token::Op(op)) => Err(format!("unexpected '{}'", op)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+
+    fn ident(&mut self, name: &str) -> Result<Expr, String> {
+        let var = match name {
+            "i" => Var::I,
+            "pc" => Var::Pc,
+            "sp" => Var::Sp,
+            "dt" => Var::Dt,
+            "st" => Var::St,
+            "op" | "opcode" => Var::Opcode,
+            "mem" | "key" => {
+                self.expect("[")?;
+                let index = Box::new(self.binary(0)?);
+                self.expect("]")?;
+                return Ok(if name == "mem" { Expr::Mem(index) } else { Expr::Key(index) });
+            }
+            _ if name.len() == 2 && name.starts_with('v') => {
+                let n = u8::from_str_radix(&name[1..], 16).map_err(|_| format!("unknown name {}", name))?;
+                Var::V(n)
+            }
+            _ => return Err(format!("unknown name {}", name)),
+        };
+        Ok(Expr::Var(var))
+    }
+}
+
+pub fn parse(text: &str) -> Result<Expr, String> {
+    let mut parser = Parser { tokens: tokenize(text)?, pos: 0 };
+    let expr = parser.binary(0)?;
+    match parser.next() {
+        None => Ok(expr),
+        Some(token) => Err(format!("unexpected {:?}", token)),
+    }
+}
+
+//Expands the {expr} placeholders of a logpoint message, {expr:x} prints the value in hex
+pub fn format(template: &str, chip: &Chip8) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let close = rest[open..].find('}').ok_or("unclosed {")? + open;
+        let inner = &rest[open + 1..close];
+        let (text, hex) = match inner.strip_suffix(":x") {
+            Some(text) => (text, true),
+            None => (inner, false),
+        };
+        let value = parse(text)?.eval(chip);
+        if hex {
+            out.push_str(&format!("{:#x}", value));
+        } else {
+            out.push_str(&value.to_string());
+        }
+        rest = &rest[close + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
@@ -0,0 +1,115 @@
+const PIXELS: usize = 64 * 32;
+//Share of a pixel's brightness left after a frame when no factor is given
+const DEFAULT_DECAY: f32 = 0.5;
+
+//How the display is presented to hide the flicker of sprites erased and drawn again with XOR
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    //every change shows up as it happens
+    Off,
+    //once per frame, as it was after the last draw of the frame that erased nothing
+    Vblank,
+    //once per frame, the pixels lit in this or the previous frame
+    Blend,
+    //pixels fade out, keeping this share of their brightness per frame
+    Decay(f32),
+}
+
+//"off", "vblank", "blend", "decay" or "decay:0.7"
+pub fn parse(text: &str) -> Result<Mode, String> {
+    let (name, factor) = match text.split_once(':') {
+        Some((name, factor)) => (name, Some(factor)),
+        None => (text, None),
+    };
+    match (name.trim().to_lowercase().as_str(), factor) {
+        ("off", None) => Ok(Mode::Off),
+        ("vblank", None) => Ok(Mode::Vblank),
+        ("blend", None) => Ok(Mode::Blend),
+        ("decay", None) => Ok(Mode::Decay(DEFAULT_DECAY)),
+        ("decay", Some(factor)) => match factor.trim().parse::<f32>() {
+            Ok(factor) if (0.0..1.0).contains(&factor) => Ok(Mode::Decay(factor)),
+            _ => Err(format!("Invalid decay {}, expected a number from 0 to below 1", factor)),
+        },
+        _ => Err(format!("Invalid anti-flicker mode {}, expected off, vblank, blend or decay[:FACTOR]", text)),
+    }
+}
+
+//The picture shown for the chip8 display; one byte per pixel, 0 is the background and 255 fully lit
+pub struct Display {
+    mode: Mode,
+    pixels: [u8; PIXELS],
+    last_draw: Option<[u8; PIXELS]>,
+    //the display after the last draw of the frame that erased nothing
+    previous: [u8; PIXELS],
+    brightness: [f32; PIXELS],
+    dirty: bool,
+}
+
+impl Display {
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.last_draw = None;
+        self.dirty = true;
+    }
+
+    //Called after CLS and DXYN, a draw that erased nothing leaves a complete picture
+    pub fn after_draw(&mut self, gfx: &[u8; PIXELS], erased: bool) {
+        if self.mode == Mode::Vblank && !erased {
+            self.last_draw = Some(*gfx);
+        }
+    }
+
+    pub fn end_frame(&mut self, gfx: &[u8; PIXELS]) {
+        let pixels = match self.mode {
+            Mode::Off => return,
+            Mode::Vblank => self.last_draw.take().unwrap_or(*gfx),
+            Mode::Blend => {
+                let mut pixels = *gfx;
+                for (pixel, previous) in pixels.iter_mut().zip(self.previous.iter()) {
+                    *pixel |= previous;
+                }
+                self.previous = *gfx;
+                pixels
+            }
+            Mode::Decay(factor) => {
+                let mut pixels = [0; PIXELS];
+                for ((pixel, brightness), lit) in pixels.iter_mut().zip(self.brightness.iter_mut()).zip(gfx.iter()) {
+                    *brightness = if *lit != 0 { 1.0 } else { *brightness * factor };
+                    *pixel = (*brightness * 255.0) as u8;
+                }
+                pixels
+            }
+        };
+        if pixels != self.pixels {
+            self.pixels = pixels;
+            self.dirty = true;
+        }
+    }
+
+    //The picture to show, the display itself when the mode is off
+    pub fn pixels<'a>(&'a self, gfx: &'a [u8; PIXELS]) -> &'a [u8; PIXELS] {
+        match self.mode {
+            Mode::Off => gfx,
+            _ => &self.pixels,
+        }
+    }
+
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::replace(&mut self.dirty, false)
+    }
+}
+
+pub fn new_display(mode: Mode) -> Display {
+    Display {
+        mode,
+        pixels: [0; PIXELS],
+        last_draw: None,
+        previous: [0; PIXELS],
+        brightness: [0.0; PIXELS],
+        dirty: true,
+    }
+}
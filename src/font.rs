@@ -0,0 +1,121 @@
+use graphics::{rectangle, types::Color, Graphics};
+use graphics::math::Matrix2d;
+
+//Built-in 3x5 bitmap font for the overlays; every row is 3 bits wide, the msb is the leftmost pixel
+pub const GLYPH_WIDTH: f64 = 3.0;
+pub const GLYPH_HEIGHT: f64 = 5.0;
+//A glyph cell includes one pixel of spacing to the right and below
+pub const CELL_WIDTH: f64 = GLYPH_WIDTH + 1.0;
+pub const CELL_HEIGHT: f64 = GLYPH_HEIGHT + 1.0;
+
+pub fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0, 0, 0, 0, 0],
+        '0' => [7, 5, 5, 5, 7],
+        '1' => [2, 6, 2, 2, 7],
+        '2' => [7, 1, 7, 4, 7],
+        '3' => [7, 1, 7, 1, 7],
+        '4' => [5, 5, 7, 1, 1],
+        '5' => [7, 4, 7, 1, 7],
+        '6' => [7, 4, 7, 5, 7],
+        '7' => [7, 1, 2, 2, 2],
+        '8' => [7, 5, 7, 5, 7],
+        '9' => [7, 5, 7, 1, 7],
+        'A' => [2, 5, 7, 5, 5],
+        'B' => [6, 5, 6, 5, 6],
+        'C' => [3, 4, 4, 4, 3],
+        'D' => [6, 5, 5, 5, 6],
+        'E' => [7, 4, 6, 4, 7],
+        'F' => [7, 4, 6, 4, 4],
+        'G' => [3, 4, 5, 5, 3],
+        'H' => [5, 5, 7, 5, 5],
+        'I' => [7, 2, 2, 2, 7],
+        'J' => [1, 1, 1, 5, 2],
+        'K' => [5, 5, 6, 5, 5],
+        'L' => [4, 4, 4, 4, 7],
+        'M' => [5, 7, 7, 5, 5],
+        'N' => [6, 5, 5, 5, 5],
+        'O' => [2, 5, 5, 5, 2],
+        'P' => [6, 5, 6, 4, 4],
+        'Q' => [2, 5, 5, 6, 3],
+        'R' => [6, 5, 6, 5, 5],
+        'S' => [3, 4, 2, 1, 6],
+        'T' => [7, 2, 2, 2, 2],
+        'U' => [5, 5, 5, 5, 7],
+        'V' => [5, 5, 5, 5, 2],
+        'W' => [5, 5, 7, 7, 5],
+        'X' => [5, 5, 2, 5, 5],
+        'Y' => [5, 5, 2, 2, 2],
+        'Z' => [7, 1, 2, 4, 7],
+        '!' => [2, 2, 2, 0, 2],
+        '"' => [5, 5, 0, 0, 0],
+        '#' => [5, 7, 5, 7, 5],
+        '$' => [3, 6, 2, 3, 6],
+        '%' => [5, 1, 2, 4, 5],
+        '&' => [2, 5, 2, 5, 3],
+        '\'' => [2, 2, 0, 0, 0],
+        '(' => [1, 2, 2, 2, 1],
+        ')' => [4, 2, 2, 2, 4],
+        '*' => [0, 5, 2, 5, 0],
+        '+' => [0, 2, 7, 2, 0],
+        ',' => [0, 0, 0, 2, 4],
+        '-' => [0, 0, 7, 0, 0],
+        '.' => [0, 0, 0, 0, 2],
+        '/' => [1, 1, 2, 4, 4],
+        ':' => [0, 2, 0, 2, 0],
+        ';' => [0, 2, 0, 2, 4],
+        '<' => [1, 2, 4, 2, 1],
+        '=' => [0, 7, 0, 7, 0],
+        '>' => [4, 2, 1, 2, 4],
+        '@' => [2, 5, 7, 4, 3],
+        '[' => [3, 2, 2, 2, 3],
+        '\\' => [4, 4, 2, 1, 1],
+        ']' => [6, 2, 2, 2, 6],
+        '^' => [2, 5, 0, 0, 0],
+        '_' => [0, 0, 0, 0, 7],
+        '`' => [4, 2, 0, 0, 0],
+        '{' => [3, 2, 4, 2, 3],
+        '|' => [2, 2, 2, 2, 2],
+        '}' => [6, 2, 1, 2, 6],
+        '~' => [0, 0, 3, 6, 0],
+        _ => [7, 1, 2, 0, 2], //'?'
+    }
+}
+
+//Draws a single line of text with its top left corner at (x, y); px is the size of one font pixel
+pub fn draw_text<G: Graphics>(text: &str, x: f64, y: f64, px: f64, color: Color, transform: Matrix2d, gl: &mut G) {
+    for (n, c) in text.chars().enumerate() {
+        let left = x + n as f64 * CELL_WIDTH * px;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH as u8 {
+                if bits & (0x4 >> col) != 0 {
+                    let rect = [left + col as f64 * px, y + row as f64 * px, px, px];
+                    rectangle(color, rect, transform, gl);
+                }
+            }
+        }
+    }
+}
+
+//Breaks a text into lines of at most `width` characters at word boundaries
+pub fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let indent: String = paragraph.chars().take_while(|c| *c == ' ').collect();
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            if !line.is_empty() && line.len() + word.len() + 1 > width {
+                lines.push(line);
+                line = String::new();
+            }
+            if line.is_empty() {
+                line.push_str(&indent);
+            } else {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+        lines.push(line);
+    }
+    lines
+}
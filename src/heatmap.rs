@@ -0,0 +1,111 @@
+use graphics::Context;
+use opengl_graphics::GlGraphics;
+
+use crate::access;
+use crate::chip8::Chip8;
+use crate::image;
+
+//The 4 KiB of memory as a square of 64 by 64 bytes, row by row
+pub const SIZE: usize = 64;
+//Share of the recent activity left after a frame, it fades out in about a second
+const DECAY: f32 = 0.93;
+//Brightness of bytes accessed at some point but not recently
+const SEEN: f32 = 0.2;
+//Brightness of other bytes that aren't zero, to show what is loaded
+const LOADED: f32 = 0.08;
+//Pixels per byte of the PNG export
+const PNG_SCALE: usize = 8;
+
+const READ: usize = 0;
+const WRITE: usize = 1;
+const EXECUTE: usize = 2;
+
+//Recent reads, writes and executes of every byte of memory, shown as blue, red and green. Self-modifying
+//code is red and green, sprite tables blue and variables red and blue.
+pub struct Heatmap {
+    recent: Vec<[f32; 3]>,
+    seen: Vec<[bool; 3]>,
+}
+
+impl Heatmap {
+    //Marks what the instruction at pc is about to access
+    pub fn record(&mut self, chip: &Chip8) {
+        self.mark(chip.pc, 2, EXECUTE);
+        let access = access::next_access(chip);
+        if let Some((start, len)) = access.mem_reads {
+            self.mark(start, len, READ);
+        }
+        if let Some((start, len)) = access.mem_writes {
+            self.mark(start, len, WRITE);
+        }
+    }
+
+    fn mark(&mut self, start: u16, len: u16, kind: usize) {
+        for addr in start as usize..(start as usize + len as usize).min(self.recent.len()) {
+            self.recent[addr][kind] = 1.0;
+            self.seen[addr][kind] = true;
+        }
+    }
+
+    //Fades the activity, once per frame
+    pub fn decay(&mut self) {
+        for cell in self.recent.iter_mut() {
+            for value in cell.iter_mut() {
+                *value *= DECAY;
+            }
+        }
+    }
+
+    pub fn color(&self, addr: usize, mem: &[u8]) -> [f32; 4] {
+        let mut channels = [0.0; 3];
+        for kind in [READ, WRITE, EXECUTE] {
+            let floor = if self.seen[addr][kind] { SEEN } else { 0.0 };
+            channels[kind] = self.recent[addr][kind].max(floor);
+        }
+        let base = if mem[addr] != 0 { LOADED } else { 0.0 };
+        [
+            channels[WRITE].max(base),
+            channels[EXECUTE].max(base),
+            channels[READ].max(base),
+            1.0,
+        ]
+    }
+
+    //Draws the map as an overlay, centered in the window
+    pub fn draw(&self, mem: &[u8], window_size: [f64; 2], c: &Context, gl: &mut GlGraphics) {
+        const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.85];
+        graphics::rectangle(BACKGROUND, [0.0, 0.0, window_size[0], window_size[1]], c.transform, gl);
+        let cell = (window_size[0].min(window_size[1]) / SIZE as f64).floor().max(1.0);
+        let left = (window_size[0] - cell * SIZE as f64) / 2.0;
+        let top = (window_size[1] - cell * SIZE as f64) / 2.0;
+        for addr in 0..SIZE * SIZE {
+            let color = self.color(addr, mem);
+            if color[..3].iter().all(|c| *c == 0.0) {
+                continue;
+            }
+            let x = left + (addr % SIZE) as f64 * cell;
+            let y = top + (addr / SIZE) as f64 * cell;
+            graphics::rectangle(color, [x, y, cell, cell], c.transform, gl);
+        }
+    }
+
+    pub fn write_png(&self, mem: &[u8], path: &str) -> Result<(), String> {
+        let width = SIZE * PNG_SCALE;
+        let mut pixels = vec![0; width * width * 3];
+        for y in 0..width {
+            for x in 0..width {
+                let addr = (y / PNG_SCALE) * SIZE + x / PNG_SCALE;
+                let offset = (y * width + x) * 3;
+                pixels[offset..offset + 3].copy_from_slice(&image::rgb(self.color(addr, mem)));
+            }
+        }
+        image::write_png(path, width as u32, width as u32, &pixels)
+    }
+}
+
+pub fn new_heatmap() -> Heatmap {
+    Heatmap {
+        recent: vec![[0.0; 3]; SIZE * SIZE],
+        seen: vec![[false; 3]; SIZE * SIZE],
+    }
+}
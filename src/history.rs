@@ -0,0 +1,91 @@
+use std::collections::VecDeque;
+
+use crate::chip8::Chip8;
+
+//Instructions between two snapshots
+pub const SNAPSHOT_INTERVAL: u64 = 1000;
+//Oldest snapshots are dropped beyond this; 500 snapshots of 1000 instructions are about 14 minutes of history at
+//the default 600 instructions per second
+const MAX_SNAPSHOTS: usize = 500;
+
+//Copy of the machine taken before the instruction number `cycles` executes. The random number
+//generator is part of the machine, so replaying from a snapshot draws the same CXKK values.
+pub struct Snapshot {
+    pub cycles: u64,
+    pub chip: Chip8,
+    pub frame_cycles: u32,
+    pub carry: u32,
+}
+
+//Something that changed the machine from outside between two instructions
+#[derive(Clone, Copy, Debug)]
+pub enum Event {
+    Key(u8, bool),
+}
+
+//Snapshots and input events recorded for the reverse execution of the debugger. Going back to an
+//instruction restores the nearest snapshot before it and re-executes up to it, applying the events.
+#[derive(Default)]
+pub struct History {
+    snapshots: VecDeque<Snapshot>,
+    events: VecDeque<(u64, Event)>,
+}
+
+impl History {
+    pub fn needs_snapshot(&self, cycles: u64) -> bool {
+        match self.snapshots.back() {
+            Some(last) => cycles >= last.cycles + SNAPSHOT_INTERVAL,
+            None => true,
+        }
+    }
+
+    pub fn record(&mut self, snapshot: Snapshot) {
+        if self.snapshots.back().is_some_and(|last| last.cycles == snapshot.cycles) {
+            self.snapshots.pop_back();
+        }
+        self.snapshots.push_back(snapshot);
+        if self.snapshots.len() > MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+            let oldest = self.oldest();
+            while self.events.front().is_some_and(|(cycles, _)| *cycles < oldest) {
+                self.events.pop_front();
+            }
+        }
+    }
+
+    pub fn record_event(&mut self, cycles: u64, event: Event) {
+        self.events.push_back((cycles, event));
+    }
+
+    //First instruction that can be gone back to
+    pub fn oldest(&self) -> u64 {
+        self.snapshots.front().map_or(0, |s| s.cycles)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    //Latest snapshot taken at or before cycles
+    pub fn nearest(&self, cycles: u64) -> Option<&Snapshot> {
+        self.snapshots.iter().rev().find(|s| s.cycles <= cycles)
+    }
+
+    pub fn snapshots_after(&self, cycles: u64) -> impl Iterator<Item = &Snapshot> {
+        self.snapshots.iter().skip_while(move |s| s.cycles <= cycles)
+    }
+
+    pub fn events_from(&self, cycles: u64) -> impl Iterator<Item = &(u64, Event)> {
+        self.events.iter().skip_while(move |(at, _)| *at < cycles)
+    }
+
+    //Forgets what happened after cycles, execution continues on a new timeline from there
+    pub fn truncate(&mut self, cycles: u64) {
+        while self.snapshots.back().is_some_and(|s| s.cycles > cycles) {
+            self.snapshots.pop_back();
+        }
+        while self.events.back().is_some_and(|(at, _)| *at > cycles) {
+            self.events.pop_back();
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use std::fs::File;
+use std::io::BufWriter;
+
+use crate::palette::{self, Palette};
+
+//Writes 8 bit RGB pixels, row by row, to a PNG file
+pub fn write_png(path: &str, width: u32, height: u32, rgb: &[u8]) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| format!("{}: {}", path, e))?;
+    writer.write_image_data(rgb).map_err(|e| format!("{}: {}", path, e))
+}
+
+//Converts a color of the graphics library to 8 bit RGB
+pub fn rgb(color: [f32; 4]) -> [u8; 3] {
+    let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    [channel(color[0]), channel(color[1]), channel(color[2])]
+}
+
+//The framebuffer in the colors of the palette as 8 bit RGB, every pixel scaled up to a square
+pub fn scale_rgb(framebuffer: &[u8], [width, height]: [usize; 2], scale: usize, palette: &Palette) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(width * height * scale * scale * 3);
+    for y in 0..height * scale {
+        for x in 0..width * scale {
+            let pixel = framebuffer.get((y / scale) * width + x / scale).copied().unwrap_or(0);
+            pixels.extend_from_slice(&rgb(palette::shade(palette, pixel)));
+        }
+    }
+    pixels
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use piston::Key;
+use serde::de::value::{Error, StrDeserializer};
+use serde::de::IntoDeserializer;
+use serde::Deserialize;
+
+use crate::keypad;
+
+pub const PRESETS: [&str; 4] = ["qwertz", "qwerty", "azerty", "numpad"];
+pub const DEFAULT: &str = "qwertz";
+
+//The keys of hex 0 to F for each layout. The four rows of keys on the left of the keyboard are the
+//keypad of the COSMAC VIP, key for key; the numpad has the digits on their own keys.
+fn preset_keys(name: &str) -> Option<[Key; 16]> {
+    use Key::*;
+    let keys = match name.to_lowercase().as_str() {
+        "qwertz" => block([[D1, D2, D3, D4], [Q, W, E, R], [A, S, D, F], [Y, X, C, V]]),
+        "qwerty" => block([[D1, D2, D3, D4], [Q, W, E, R], [A, S, D, F], [Z, X, C, V]]),
+        "azerty" => block([[D1, D2, D3, D4], [A, Z, E, R], [Q, S, D, F], [W, X, C, V]]),
+        //A to F around the digits
+        "numpad" => [NumPad0, NumPad1, NumPad2, NumPad3, NumPad4, NumPad5, NumPad6, NumPad7, NumPad8, NumPad9,
+            NumPadDivide, NumPadMultiply, NumPadMinus, NumPadPlus, NumPadEnter, NumPadPeriod],
+        _ => return None,
+    };
+    Some(keys)
+}
+
+//The hex keys of a block of keys laid out like the VIP's keypad
+fn block(rows: [[Key; 4]; 4]) -> [Key; 16] {
+    let mut keys = [Key::Unknown; 16];
+    for (row, hexes) in keypad::LAYOUT.iter().enumerate() {
+        for (column, hex) in hexes.iter().enumerate() {
+            keys[*hex as usize] = rows[row][column];
+        }
+    }
+    keys
+}
+
+//Keys of the keyboard and the hex keys they press. A hex key can have several keys.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Keymap {
+    keys: HashMap<Key, u8>,
+}
+
+impl Keymap {
+    pub fn get(&self, key: Key) -> Option<u8> {
+        self.keys.get(&key).copied()
+    }
+
+    //The keys of a hex key in the order of their key codes
+    pub fn keys_of(&self, hex: u8) -> Vec<Key> {
+        let mut keys: Vec<Key> = self.keys.iter().filter(|(_, h)| **h == hex).map(|(key, _)| *key).collect();
+        keys.sort_unstable();
+        keys
+    }
+
+    pub fn bind(&mut self, key: Key, hex: u8) {
+        self.keys.insert(key, hex & 0xf);
+    }
+
+    //Makes key the only key of a hex key
+    pub fn rebind(&mut self, hex: u8, key: Key) {
+        self.keys.retain(|_, h| *h != hex);
+        self.bind(key, hex);
+    }
+
+    //Adds the bindings of another keymap, they replace the ones of the same keys
+    pub fn extend(&mut self, other: &Keymap) {
+        for (key, hex) in &other.keys {
+            self.keys.insert(*key, *hex);
+        }
+    }
+
+    //Key names and hex keys as they are written in the config
+    pub fn to_names(&self) -> HashMap<String, u8> {
+        self.keys.iter().map(|(key, hex)| (key_name(*key), *hex)).collect()
+    }
+}
+
+pub fn preset(name: &str) -> Result<Keymap, String> {
+    let keys = preset_keys(name).ok_or_else(|| format!("Unknown keymap {}, expected one of {}", name, PRESETS.join(", ")))?;
+    let mut keymap = Keymap::default();
+    for (hex, key) in keys.iter().enumerate() {
+        keymap.bind(*key, hex as u8);
+    }
+    Ok(keymap)
+}
+
+//Bindings of the config or the rom database, like { Up = 2, Space = 5 }
+pub fn from_names(names: &HashMap<String, u8>) -> Result<Keymap, String> {
+    let mut keymap = Keymap::default();
+    for (name, hex) in names {
+        let key = parse_key(name).ok_or_else(|| format!("Unknown key {}", name))?;
+        if *hex > 0xf {
+            return Err(format!("{} is bound to {}, hex keys go from 0 to 15", name, hex));
+        }
+        keymap.bind(key, *hex);
+    }
+    Ok(keymap)
+}
+
+//The names are the ones of piston's keys: D1, Q, Up, Space, NumPad5
+pub fn parse_key(name: &str) -> Option<Key> {
+    let deserializer: StrDeserializer<Error> = name.into_deserializer();
+    Key::deserialize(deserializer).ok().filter(|key| *key != Key::Unknown)
+}
+
+pub fn key_name(key: Key) -> String {
+    match serde_json::to_value(key) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+//A short name to print on a button: "1" for D1 and "N5" for NumPad5
+pub fn label(key: Key) -> String {
+    let name = key_name(key);
+    if let Some(digit) = name.strip_prefix('D').filter(|rest| rest.len() == 1) {
+        return digit.to_string();
+    }
+    match name.strip_prefix("NumPad") {
+        Some(rest) => format!("N{}", rest).to_uppercase(),
+        None => name.to_uppercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_follow_the_keypad() {
+        let qwerty = preset("qwerty").unwrap();
+        assert_eq!(qwerty.get(Key::D1), Some(0x1));
+        assert_eq!(qwerty.get(Key::D4), Some(0xc));
+        assert_eq!(qwerty.get(Key::W), Some(0x5));
+        assert_eq!(qwerty.get(Key::X), Some(0x0));
+        assert_eq!(qwerty.get(Key::V), Some(0xf));
+        for name in PRESETS {
+            let keymap = preset(name).unwrap();
+            assert!((0..16).all(|hex| keymap.keys_of(hex).len() == 1), "{}", name);
+        }
+    }
+}
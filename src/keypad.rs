@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+use graphics::{rectangle, Context, Graphics};
+
+use crate::font;
+use crate::platform::Platform;
+
+//The hex keypad as it is laid out on the COSMAC VIP
+pub const LAYOUT: [[u8; 4]; 4] = [[0x1, 0x2, 0x3, 0xc], [0x4, 0x5, 0x6, 0xd], [0x7, 0x8, 0x9, 0xe], [0xa, 0x0, 0xb, 0xf]];
+//Pointer id of the mouse, touches have their own
+pub const MOUSE: i64 = -1;
+const MARGIN: f64 = 8.0;
+const GAP: f64 = 4.0;
+const MAX_BUTTON: f64 = 56.0;
+const BUTTON: [f32; 4] = [0.2, 0.2, 0.23, 0.85];
+const POLLED: [f32; 4] = [0.2, 0.4, 0.75, 0.9];
+//a key the game checked during the last frames
+const PRESSED: [f32; 4] = [0.95, 0.95, 0.95, 1.0];
+const TEXT: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+const TEXT_PRESSED: [f32; 4] = [0.05, 0.05, 0.05, 1.0];
+const BINDING: [f32; 4] = [0.6, 0.6, 0.65, 1.0];
+
+//Left, top and size of the buttons in the bottom right corner of the window
+fn layout(window_size: [f64; 2]) -> (f64, f64, f64) {
+    let size = ((window_size[1].min(window_size[0]) / 2.0 - MARGIN) / 4.0 - GAP).clamp(8.0, MAX_BUTTON);
+    let width = 4.0 * (size + GAP) - GAP;
+    (window_size[0] - MARGIN - width, window_size[1] - MARGIN - width, size)
+}
+
+//A keypad on the screen that is clicked or touched. Every pointer holds the key it went down on until
+//it is lifted or moves to another button.
+#[derive(Default)]
+pub struct Keypad {
+    held: HashMap<i64, u8>,
+    //keys by the mouse or touch holding them
+}
+
+impl Keypad {
+    pub fn key_at(&self, position: [f64; 2], window_size: [f64; 2]) -> Option<u8> {
+        let (left, top, size) = layout(window_size);
+        let column = ((position[0] - left) / (size + GAP)).floor();
+        let row = ((position[1] - top) / (size + GAP)).floor();
+        if !(0.0..4.0).contains(&column) || !(0.0..4.0).contains(&row) {
+            return None;
+        }
+        //The gaps between the buttons are no button
+        let inside = |offset: f64| offset - (offset / (size + GAP)).floor() * (size + GAP) < size;
+        if !inside(position[0] - left) || !inside(position[1] - top) {
+            return None;
+        }
+        Some(LAYOUT[row as usize][column as usize])
+    }
+
+    //Presses the button under a pointer; returns whether it was on the keypad
+    pub fn press(&mut self, pointer: i64, position: [f64; 2], platform: &mut Platform, window_size: [f64; 2]) -> bool {
+        let key = self.key_at(position, window_size);
+        if self.held.get(&pointer).copied() != key {
+            self.release(pointer, platform);
+            if let Some(key) = key {
+                platform.set_key(key, true);
+                self.held.insert(pointer, key);
+            }
+        }
+        key.is_some()
+    }
+
+    //A pointer that is down moved, it slides over to the button under it
+    pub fn slide(&mut self, pointer: i64, position: [f64; 2], platform: &mut Platform, window_size: [f64; 2]) {
+        if self.held.contains_key(&pointer) {
+            self.press(pointer, position, platform, window_size);
+        }
+    }
+
+    pub fn release(&mut self, pointer: i64, platform: &mut Platform) {
+        if let Some(key) = self.held.remove(&pointer) {
+            //Another pointer might still hold the same key
+            if !self.held.values().any(|held| *held == key) {
+                platform.set_key(key, false);
+            }
+        }
+    }
+
+    //Lets go of every key, when the keypad is hidden
+    pub fn release_all(&mut self, platform: &mut Platform) {
+        for key in self.held.drain().map(|(_, key)| key).collect::<Vec<u8>>() {
+            platform.set_key(key, false);
+        }
+    }
+
+    //Every button shows its hex digit and the keyboard key bound to it
+    pub fn draw<G: Graphics>(&self, platform: &Platform, window_size: [f64; 2], c: &Context, gl: &mut G) {
+        let (left, top, size) = layout(window_size);
+        let px = (size / 14.0).floor().max(1.0);
+        let small = (px / 2.0).floor().max(1.0);
+        let polled = platform.polled_keys();
+        for (row, keys) in LAYOUT.iter().enumerate() {
+            for (column, key) in keys.iter().enumerate() {
+                let x = left + column as f64 * (size + GAP);
+                let y = top + row as f64 * (size + GAP);
+                let pressed = platform.chip().keypad[*key as usize] != 0;
+                let (color, text) = if pressed {
+                    (PRESSED, TEXT_PRESSED)
+                } else if polled[*key as usize] {
+                    (POLLED, TEXT)
+                } else {
+                    (BUTTON, TEXT)
+                };
+                rectangle(color, [x, y, size, size], c.transform, gl);
+                let digit_x = x + (size - font::GLYPH_WIDTH * px) / 2.0;
+                let digit_y = y + (size - font::GLYPH_HEIGHT * px) / 2.0 - font::CELL_HEIGHT * small / 2.0;
+                font::draw_text(&format!("{:X}", key), digit_x, digit_y, px, text, c.transform, gl);
+                let binding = platform.key_label(*key);
+                let width = binding.len() as f64 * font::CELL_WIDTH * small - small;
+                let binding_color = if pressed { TEXT_PRESSED } else { BINDING };
+                font::draw_text(&binding, x + (size - width) / 2.0, y + size - font::CELL_HEIGHT * small - small,
+                    small, binding_color, c.transform, gl);
+            }
+        }
+    }
+}
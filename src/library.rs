@@ -0,0 +1,182 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use graphics::{rectangle, Context, Graphics};
+
+use crate::config;
+use crate::font;
+use crate::metadata::{self, RomInfo};
+
+const ROM_EXTENSIONS: [&str; 3] = ["ch8", "c8", "rom"];
+const MAX_RECENT: usize = 8;
+
+const BACKGROUND: [f32; 4] = [0.05, 0.05, 0.08, 1.0];
+const TEXT: [f32; 4] = [0.85, 0.85, 0.85, 1.0];
+const DIM: [f32; 4] = [0.5, 0.5, 0.55, 1.0];
+const HIGHLIGHT: [f32; 4] = [0.2, 0.35, 0.6, 1.0];
+const PX: f64 = 3.0;
+const MARGIN: f64 = 20.0;
+
+pub struct LibraryEntry {
+    pub path: PathBuf,
+    pub info: RomInfo,
+}
+
+impl LibraryEntry {
+    fn label(&self) -> String {
+        let mut label = self.info.title.clone();
+        match (&self.info.author, self.info.year) {
+            (Some(author), Some(year)) => label.push_str(&format!("  -  {}, {}", author, year)),
+            (Some(author), None) => label.push_str(&format!("  -  {}", author)),
+            (None, Some(year)) => label.push_str(&format!("  -  {}", year)),
+            (None, None) => {}
+        }
+        label
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        self.label().to_lowercase().contains(&query.to_lowercase())
+    }
+}
+
+pub struct Library {
+    entries: Vec<LibraryEntry>,
+    recent: Vec<PathBuf>,
+    query: String,
+    selected: usize,
+}
+
+impl Library {
+    //The browser lists the recently played roms first while nothing is searched for
+    fn visible(&self) -> Vec<&LibraryEntry> {
+        let mut visible: Vec<&LibraryEntry> = Vec::new();
+        if self.query.is_empty() {
+            for path in &self.recent {
+                if let Some(entry) = self.entries.iter().find(|e| &e.path == path) {
+                    visible.push(entry);
+                }
+            }
+        }
+        visible.extend(self.entries.iter().filter(|e| e.matches(&self.query)));
+        visible
+    }
+
+    fn recent_count(&self) -> usize {
+        if self.query.is_empty() {
+            self.recent.iter().filter(|p| self.entries.iter().any(|e| &e.path == *p)).count()
+        } else {
+            0
+        }
+    }
+
+    pub fn type_text(&mut self, text: &str) {
+        self.query.extend(text.chars().filter(|c| !c.is_control()));
+        self.selected = 0;
+    }
+
+    pub fn backspace(&mut self) {
+        self.query.pop();
+        self.selected = 0;
+    }
+
+    pub fn move_selection(&mut self, delta: i64) {
+        let len = self.visible().len() as i64;
+        if len > 0 {
+            self.selected = (self.selected as i64 + delta).clamp(0, len - 1) as usize;
+        }
+    }
+
+    pub fn selected_path(&self) -> Option<PathBuf> {
+        self.visible().get(self.selected).map(|e| e.path.clone())
+    }
+
+    //Moves the rom to the top of the recently played list and persists the list
+    pub fn mark_played(&mut self, path: &Path) {
+        self.recent.retain(|p| p != path);
+        self.recent.insert(0, path.to_path_buf());
+        self.recent.truncate(MAX_RECENT);
+        self.query.clear();
+        self.selected = 0;
+
+        if let Some(file) = recent_path() {
+            let lines: Vec<String> = self.recent.iter().map(|p| p.display().to_string()).collect();
+            let saved = file.parent().map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::write(&file, lines.join("\n")));
+            if let Err(e) = saved {
+                println!("Error saving {}: {}", file.display(), e);
+            }
+        }
+    }
+
+    pub fn draw<G: Graphics>(&self, window_size: [f64; 2], c: &Context, gl: &mut G) {
+        let row_height = font::CELL_HEIGHT * PX;
+        let columns = ((window_size[0] - 2.0 * MARGIN) / (font::CELL_WIDTH * PX)) as usize;
+
+        rectangle(BACKGROUND, [0.0, 0.0, window_size[0], window_size[1]], c.transform, gl);
+        font::draw_text("RUST_8 ROM LIBRARY", MARGIN, MARGIN, PX, TEXT, c.transform, gl);
+        let search = format!("Search: {}_", self.query);
+        font::draw_text(&search, MARGIN, MARGIN + 2.0 * row_height, PX, TEXT, c.transform, gl);
+        let help = "Up/Down select, Enter play, Esc quit; Esc in game returns here";
+        font::draw_text(help, MARGIN, window_size[1] - MARGIN - row_height, PX, DIM, c.transform, gl);
+
+        let visible = self.visible();
+        let recent_count = self.recent_count();
+        let list_top = MARGIN + 4.0 * row_height;
+        let rows = ((window_size[1] - list_top - MARGIN - 2.0 * row_height) / row_height).max(1.0) as usize;
+
+        //Scroll so that the selection stays in view
+        let first = self.selected.saturating_sub(rows - 1);
+        for (row, index) in (first..visible.len()).take(rows).enumerate() {
+            let y = list_top + row as f64 * row_height;
+            if index == self.selected {
+                rectangle(HIGHLIGHT, [MARGIN - 4.0, y - PX, window_size[0] - 2.0 * MARGIN + 8.0, row_height], c.transform, gl);
+            }
+            let marker = if index < recent_count { "* " } else { "  " };
+            let label: String = format!("{}{}", marker, visible[index].label()).chars().take(columns).collect();
+            font::draw_text(&label, MARGIN, y, PX, TEXT, c.transform, gl);
+        }
+        if visible.is_empty() {
+            font::draw_text("No roms found", MARGIN, list_top, PX, DIM, c.transform, gl);
+        }
+    }
+}
+
+fn recent_path() -> Option<PathBuf> {
+    config::config_dir().map(|dir| dir.join("recent.txt"))
+}
+
+fn load_recent() -> Vec<PathBuf> {
+    recent_path()
+        .and_then(|file| fs::read_to_string(file).ok())
+        .map(|text| text.lines().filter(|l| !l.is_empty()).map(PathBuf::from).collect())
+        .unwrap_or_default()
+}
+
+fn is_rom(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| ROM_EXTENSIONS.contains(&ext.as_str()))
+}
+
+//Collects the roms in dir together with their metadata, sorted by title
+pub fn scan(dir: &Path) -> Library {
+    let mut entries: Vec<LibraryEntry> = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| is_rom(path))
+            .map(|path| LibraryEntry { info: metadata::load_info(&path), path })
+            .collect(),
+        Err(e) => {
+            println!("Error reading rom directory {}: {}", dir.display(), e);
+            Vec::new()
+        }
+    };
+    entries.sort_by_key(|e| (e.info.title.to_lowercase(), e.path.clone()));
+
+    Library {
+        entries,
+        recent: load_recent(),
+        query: String::new(),
+        selected: 0,
+    }
+}
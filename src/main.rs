@@ -4,109 +4,834 @@ extern crate opengl_graphics;
 extern crate piston;
 extern crate clap;
 
+use glutin::window::Fullscreen;
 use glutin_window::GlutinWindow as Window;
 use opengl_graphics::{GlGraphics, OpenGL};
-use piston::event_loop::{Events, EventSettings};
+use piston::event_loop::{EventLoop, Events, EventSettings};
 use piston::input::{RenderArgs, RenderEvent, UpdateArgs, UpdateEvent};
-use piston::window::WindowSettings;
-use piston::{Button, PressEvent, ReleaseEvent};
+use piston::window::{AdvancedWindow, Window as _, WindowSettings};
+use piston::{Button, FocusEvent, Key, MouseButton, MouseCursorEvent, PressEvent, ReleaseEvent, TextEvent, Touch, TouchArgs, TouchEvent};
+use std::path::{Path, PathBuf};
+use crate::dap::Dap;
+use crate::debugger::Debugger;
+use crate::keymap::Keymap;
+use crate::keypad::Keypad;
+use crate::library::Library;
+use crate::metadata::RomInfo;
+use crate::osd::Osd;
 use crate::platform::Platform;
+use crate::screen::Screen;
+use crate::viewer::Viewer;
 
+mod access;
+mod asm;
+mod breakpoints;
 mod chip8;
+mod cli;
+mod config;
+mod coverage;
+mod dap;
+mod debugger;
+mod disasm;
+mod expr;
+mod flicker;
+mod font;
+mod heatmap;
+mod history;
+mod image;
+mod keymap;
+mod keypad;
+mod library;
+mod metadata;
+mod osd;
+mod palette;
 mod platform;
+mod profile;
+mod record;
+mod romdb;
+mod script;
+mod screen;
+mod symbols;
+mod terminal;
+mod trace;
+mod viewer;
 
 pub struct App {
     gl: GlGraphics,
     // OpenGL drawing backend.
-    rotation: f64,  // Rotation for the square.
-    platform: Platform
+    platform: Platform,
+    library: Library,
+    info: Option<RomInfo>,
+    //metadata of the running rom; None while the library browser is shown
+    show_info: bool,
+    show_heatmap: bool,
+    viewer: Option<Viewer>,
+    //the memory viewer when it is shown
+    keypad: Option<Keypad>,
+    //the clickable keypad when it is shown
+    rebind: Option<(usize, Keymap)>,
+    //the position on the keypad being rebound and the keys so far
+    window_size: [f64; 2],
+    mouse: [f64; 2],
+    quit: bool,
+    scale: f64,
+    //window pixels per chip8 pixel the window starts with
+    resolution: [usize; 2],
+    //of the screen the window was last sized for
+    toggle_fullscreen: bool,
+    screen: Screen,
+    redraw: bool,
+    //something besides the chip8 screen changed what the window shows
+    osd: Osd,
+    debugger: Option<Debugger>,
+    dap: Option<Dap>,
 }
 
-const SCALE: f64 = 20.0;
+const DEFAULT_SCALE: f64 = 20.0;
 const VIDEO_WIDTH: f64 = 64.0;
 const VIDEO_HEIGHT: f64 = 32.0;
 
 impl App {
-    fn render(&mut self, args: &RenderArgs) {
+    //Draws a frame and returns whether it did. The game alone is only drawn again when its screen changed,
+    //the library and the debug overlays every time.
+    fn render(&mut self, args: &RenderArgs) -> bool {
         use graphics::*;
 
-
-        const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
-        const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
-        const LINE_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.0];
-
-        //See Grid
-        let grid = grid::Grid{
-            cols: 64,
-            rows: 32,
-            units: SCALE
+        let resized = self.window_size != args.window_size;
+        self.window_size = args.window_size;
+        let redraw = std::mem::replace(&mut self.redraw, false) || resized;
+        let info = match &self.info {
+            Some(info) => info,
+            None => {
+                let library = &self.library;
+                self.gl.draw(args.viewport(), |c, gl| library.draw(args.window_size, &c, gl));
+                return true;
+            }
         };
-        let line = Line::new(LINE_COLOR, 0.5);
-        let platform = &mut self.platform;
 
+        let palette = self.platform.palette();
+        let screen_changed = self.platform.take_screen_dirty() || palette != self.screen.palette();
+        if screen_changed {
+            self.screen.update(self.platform.framebuffer(), self.platform.resolution(), palette);
+        }
+        let osd_active = self.osd.active();
+        if !(redraw || screen_changed || self.show_heatmap || self.viewer.is_some() || self.keypad.is_some() || osd_active) {
+            return false;
+        }
+        self.osd.frame(self.platform.cycles());
+        let paused = self.debugger.as_ref().is_some_and(|d| d.paused()) || self.dap.as_ref().is_some_and(|d| d.paused());
+        let status = osd::Status {
+            paused,
+            recording: self.platform.recording(),
+            pressed: self.platform.chip().keypad.map(|k| k != 0),
+            polled: self.platform.polled_keys(),
+            ips: self.platform.ips(),
+            keypad: self.keypad.is_some(),
+        };
 
+        let rect = self.screen.fit(args.window_size, args.draw_size);
+        let platform = &self.platform;
+        let screen = &self.screen;
+        let show_info = self.show_info;
+        let show_heatmap = self.show_heatmap;
+        let viewer = &self.viewer;
+        let osd = &self.osd;
+        let keypad = &self.keypad;
+        let rebind = self.rebind.as_ref().map(|(n, keymap)| {
+            let hex = keypad::LAYOUT[n / 4][n % 4];
+            (hex, keymap.keys_of(hex).first().map(|key| keymap::label(*key)).unwrap_or_default())
+        });
         self.gl.draw(args.viewport(), |c, gl| {
-            // Clear the screen.
-            clear(BLACK, gl);
+            //Black bars fill the window around the screen
+            clear([0.0, 0.0, 0.0, 1.0], gl);
+            screen.draw(rect, &c, gl);
+            if let Some(keypad) = keypad {
+                keypad.draw(platform, args.window_size, &c, gl);
+            }
 
-            grid.draw(&line, &c.draw_state, c.transform, gl);
-            // Draw a box rotating around the middle of the screen.
-            // rectangle(WHITE, square, transform, gl);
-            for (x,y) in grid.cells(){
-                let mut col = BLACK;
-                if platform.get_gfx()[(y as usize * VIDEO_WIDTH as usize) + x as usize] != 0 {
-                    col = WHITE;
+            if show_heatmap {
+                if let Some(heatmap) = platform.heatmap() {
+                    heatmap.draw(&platform.chip().mem, args.window_size, &c, gl);
                 }
-
-                rectangle(col, rectangle::square(x as f64* SCALE, y as f64 * SCALE, SCALE), c.transform, gl);
             }
+            if let Some(viewer) = viewer {
+                viewer.draw(platform.chip(), args.window_size, &c, gl);
+            }
+            if show_info {
+                draw_info(info, args.window_size, &c, gl);
+            }
+            if let Some((hex, current)) = &rebind {
+                draw_rebind(*hex, current, args.window_size, &c, gl);
+            }
+            osd.draw(&status, args.window_size, &c, gl);
         });
+        true
+    }
+
+    fn title(&self) -> String {
+        match &self.info {
+            Some(info) if self.platform.recording() => format!("rust_8 - {} (recording)", info.title),
+            Some(info) => format!("rust_8 - {}", info.title),
+            None => "rust_8".to_string(),
+        }
+    }
+
+    fn update(&mut self, _args: &UpdateArgs) {
+        if let Some(dap) = &mut self.dap {
+            //A launch request leaves the library browser like picking the rom there
+            dap.handle_requests(&mut self.platform);
+            if let Some(rom) = dap.take_launched() {
+                self.info = Some(metadata::load_info(&rom));
+                self.library.mark_played(&rom);
+            }
+            if self.info.is_some() {
+                dap.run(&mut self.platform);
+            }
+            return;
+        }
+        if self.info.is_some() {
+            match &mut self.debugger {
+                Some(debugger) => {
+                    debugger.update(&mut self.platform);
+                }
+                None => self.platform.c8_cycle(),
+            }
+        }
+    }
+
+    //Loads the rom into a fresh machine and leaves the library browser
+    fn launch(&mut self, rom: &Path) {
+        self.platform.open_rom(&rom.display().to_string());
+        self.info = Some(metadata::load_info(rom));
+        self.library.mark_played(rom);
+        if let Some(name) = self.platform.chip().quirks().name() {
+            self.notify(&format!("Quirks: {}", name.to_uppercase()));
+        }
     }
 
-    fn update(&mut self, args: &UpdateArgs) {
-        self.platform.c8_cycle();
+    //The mouse moved; a button of the keypad held with it follows
+    fn move_mouse(&mut self, position: [f64; 2]) {
+        self.mouse = position;
+        if let Some(keypad) = &mut self.keypad {
+            keypad.slide(keypad::MOUSE, position, &mut self.platform, self.window_size);
+        }
+    }
+
+    fn touch(&mut self, args: &TouchArgs) {
+        let keypad = match &mut self.keypad {
+            Some(keypad) if self.info.is_some() => keypad,
+            _ => return,
+        };
+        self.redraw = true;
+        match args.touch {
+            Touch::Start => {
+                keypad.press(args.id, args.position(), &mut self.platform, self.window_size);
+            }
+            Touch::Move => keypad.slide(args.id, args.position(), &mut self.platform, self.window_size),
+            Touch::End | Touch::Cancel => keypad.release(args.id, &mut self.platform),
+        }
+    }
+
+    //Binds a key to the hex key asked for and goes on to the next one, in the order of the keypad
+    fn rebind_key(&mut self, key: Key) {
+        let (n, mut keymap) = match self.rebind.take() {
+            Some(rebind) => rebind,
+            None => return,
+        };
+        match key {
+            Key::Escape => {
+                self.notify("Rebinding canceled");
+                return;
+            }
+            Key::Backspace => {}
+            _ => keymap.rebind(keypad::LAYOUT[n / 4][n % 4], key),
+        }
+        if n + 1 < 16 {
+            self.rebind = Some((n + 1, keymap));
+        } else {
+            self.platform.set_keymap(keymap);
+            self.notify("Keys rebound, F5 saves them for this rom");
+        }
+    }
+
+    //Prints a message and shows it on the OSD for a while
+    fn notify(&mut self, message: &str) {
+        println!("{}", message);
+        self.osd.message(message);
+    }
+
+    fn set_library_input(&mut self, btn: Button) {
+        match btn {
+            Button::Keyboard(Key::Up) => self.library.move_selection(-1),
+            Button::Keyboard(Key::Down) => self.library.move_selection(1),
+            Button::Keyboard(Key::PageUp) => self.library.move_selection(-10),
+            Button::Keyboard(Key::PageDown) => self.library.move_selection(10),
+            Button::Keyboard(Key::Backspace) => self.library.backspace(),
+            Button::Keyboard(Key::Return) => {
+                if let Some(rom) = self.library.selected_path() {
+                    self.launch(&rom);
+                }
+            },
+            Button::Keyboard(Key::Escape) => self.quit = true,
+            _ => {}
+        }
     }
 
     fn set_input(&mut self, btn: Button, is_pressed: bool){
+        self.redraw = true;
+        if self.info.is_none() {
+            if is_pressed {
+                self.set_library_input(btn);
+            }
+            return;
+        }
+        //Rebinding takes every key until the last hex key got one
+        if self.rebind.is_some() {
+            if let (true, Button::Keyboard(key)) = (is_pressed, btn) {
+                self.rebind_key(key);
+            }
+            return;
+        }
+
         match btn {
-            Button::Keyboard(key) => {
-                let input_code = format!("{:?}", key);
-                println!("{}", input_code);
-                self.platform.handle_input(&input_code, is_pressed);
+            Button::Keyboard(Key::Escape) => {
+                if is_pressed {
+                    self.info = None;
+                    self.show_info = false;
+                    self.show_heatmap = false;
+                    self.viewer = None;
+                }
             },
-            _ => println!("Something else")
+            Button::Keyboard(Key::F4) => {
+                if is_pressed {
+                    let name = self.platform.cycle_palette();
+                    self.notify(&format!("Palette {}, F5 saves it for this rom", name));
+                }
+            },
+            Button::Keyboard(Key::F12) => {
+                if is_pressed {
+                    let path = self.platform.capture_name("png");
+                    match self.platform.screenshot(&path) {
+                        Ok(()) => self.notify(&format!("Saved a screenshot to {}", path)),
+                        Err(e) => self.notify(&e),
+                    }
+                }
+            },
+            Button::Keyboard(Key::F9) => {
+                if is_pressed {
+                    if let Some(message) = self.platform.stop_recording() {
+                        self.notify(&message);
+                    } else {
+                        let path = self.platform.capture_name("gif");
+                        match self.platform.start_recording(&path) {
+                            Ok(()) => self.notify(&format!("Recording to {}, F9 stops", path)),
+                            Err(e) => self.notify(&e),
+                        }
+                    }
+                }
+            },
+            Button::Keyboard(Key::F6) => {
+                if is_pressed {
+                    self.osd.toggle();
+                }
+            },
+            Button::Keyboard(Key::F7) => {
+                if is_pressed {
+                    match self.keypad.take() {
+                        Some(mut keypad) => keypad.release_all(&mut self.platform),
+                        None => self.keypad = Some(Keypad::default()),
+                    }
+                }
+            },
+            Button::Keyboard(Key::F8) => {
+                if is_pressed {
+                    self.rebind = Some((0, self.platform.keymap().clone()));
+                }
+            },
+            Button::Keyboard(Key::F11) => {
+                if is_pressed {
+                    self.toggle_fullscreen = true;
+                }
+            },
+            Button::Keyboard(Key::F5) => {
+                if is_pressed {
+                    match self.platform.save_rom_settings() {
+                        Ok(message) => self.notify(&message),
+                        Err(e) => self.notify(&e),
+                    }
+                }
+            },
+            Button::Keyboard(Key::F1) => {
+                if is_pressed {
+                    self.show_info = !self.show_info;
+                }
+            },
+            Button::Keyboard(Key::F2) => {
+                if is_pressed {
+                    self.platform.enable_heatmap();
+                    self.show_heatmap = !self.show_heatmap;
+                }
+            },
+            Button::Keyboard(Key::F3) => {
+                if is_pressed {
+                    self.viewer = match self.viewer {
+                        Some(_) => None,
+                        None => Some(viewer::new_viewer()),
+                    };
+                }
+            },
+            //The memory viewer takes the keyboard and mouse while it is shown
+            Button::Keyboard(key) if self.viewer.is_some() => {
+                if let (true, Some(viewer)) = (is_pressed, &mut self.viewer) {
+                    viewer.press(key, &mut self.platform, self.window_size);
+                }
+            },
+            Button::Mouse(MouseButton::Left) if self.viewer.is_some() => {
+                if let (true, Some(viewer)) = (is_pressed, &mut self.viewer) {
+                    viewer.click(self.mouse, &mut self.platform, self.window_size);
+                }
+            },
+            Button::Mouse(MouseButton::Left) if self.keypad.is_some() => {
+                if let Some(keypad) = &mut self.keypad {
+                    if is_pressed {
+                        keypad.press(keypad::MOUSE, self.mouse, &mut self.platform, self.window_size);
+                    } else {
+                        keypad.release(keypad::MOUSE, &mut self.platform);
+                    }
+                }
+            },
+            Button::Keyboard(key) => self.platform.handle_input(key, is_pressed),
+            Button::Mouse(_) | Button::Controller(_) | Button::Hat(_) => {}
+        }
+    }
+}
+
+//Draws the rom metadata in a translucent box over the game
+fn draw_info(info: &RomInfo, window_size: [f64; 2], c: &graphics::Context, gl: &mut GlGraphics) {
+    const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.85];
+    const TEXT: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+    const PX: f64 = 3.0;
+    const MARGIN: f64 = 20.0;
+
+    graphics::rectangle(BACKGROUND, [0.0, 0.0, window_size[0], window_size[1]], c.transform, gl);
+    let columns = ((window_size[0] - 2.0 * MARGIN) / (font::CELL_WIDTH * PX)) as usize;
+    let rows = ((window_size[1] - 2.0 * MARGIN) / (font::CELL_HEIGHT * PX)) as usize;
+    let text = info.lines().join("\n");
+    for (row, line) in font::wrap(&text, columns).iter().take(rows).enumerate() {
+        let y = MARGIN + row as f64 * font::CELL_HEIGHT * PX;
+        font::draw_text(line, MARGIN, y, PX, TEXT, c.transform, gl);
+    }
+}
+
+//The prompt of the key rebinding across the middle of the window
+fn draw_rebind(hex: u8, current: &str, window_size: [f64; 2], c: &graphics::Context, gl: &mut GlGraphics) {
+    const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.85];
+    const TEXT: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+    const PX: f64 = 3.0;
+    const MARGIN: f64 = 20.0;
+
+    let lines = [
+        format!("Press the key for hex {:X}", hex),
+        if current.is_empty() { "Backspace skips it, Esc cancels".to_string() } else { format!("Backspace keeps {}, Esc cancels", current) },
+    ];
+    let row_height = font::CELL_HEIGHT * PX;
+    let top = (window_size[1] - 2.0 * row_height) / 2.0;
+    graphics::rectangle(BACKGROUND, [0.0, top - MARGIN, window_size[0], 2.0 * row_height + 2.0 * MARGIN], c.transform, gl);
+    for (row, line) in lines.iter().enumerate() {
+        let x = (window_size[0] - line.len() as f64 * font::CELL_WIDTH * PX) / 2.0;
+        font::draw_text(line, x.max(MARGIN), top + row as f64 * row_height, PX, TEXT, c.transform, gl);
+    }
+}
+
+fn print_info(rom: &str) {
+    let info = metadata::load_info(Path::new(rom));
+    for line in info.lines() {
+        println!("{}", line);
+    }
+
+    let data = std::fs::read(rom).expect("Error opening rom");
+    let hash = romdb::sha1_hex(&data);
+    println!();
+    println!("SHA-1:  {}", hash);
+    if let Some(entry) = romdb::load_db().lookup(&hash) {
+        println!("Rom database entry: {:?}", entry);
+    }
+}
+
+//A platform running the rom without a window, with the tools asked for on the command line
+fn open_platform(rom: &str, options: cli::Options, config: config::Config, matches: &clap::ArgMatches) -> Platform {
+    let mut platform = platform::new_platform();
+    if let Some(tracer) = open_tracer(matches) {
+        platform.set_tracer(tracer);
+    }
+    if profiling(matches) {
+        platform.enable_profiler();
+    }
+    if matches.is_present("coverage") {
+        platform.enable_coverage();
+    }
+    platform.set_options(options);
+    platform.set_config(config);
+    platform.open_rom(rom);
+    start_recording(&mut platform, matches);
+    platform
+}
+
+//Writes what the tools collected once the rom stopped
+fn finish_platform(platform: &mut Platform, matches: &clap::ArgMatches) {
+    write_profile(platform, matches);
+    save_coverage(platform, matches);
+    stop_recording(platform);
+}
+
+fn run_headless(rom: &str, options: cli::Options, config: config::Config, frames: u32, debug: bool, dap: Option<Dap>, matches: &clap::ArgMatches) {
+    let mut platform = open_platform(rom, options, config, matches);
+    let heatmap = matches.subcommand_matches("headless").and_then(|sub| sub.get_one::<String>("heatmap"));
+    if heatmap.is_some() {
+        platform.enable_heatmap();
+    }
+    let script = match matches.subcommand_matches("headless").and_then(|sub| sub.get_one::<String>("input")) {
+        Some(path) => match script::load(path) {
+            Ok(script) => Some(script),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    if let Some(mut dap) = dap {
+        let mut frame = 0;
+        while frame < frames {
+            if dap.update(&mut platform) {
+                frame += 1;
+            } else {
+                std::thread::sleep(std::time::Duration::from_millis(16));
+            }
+        }
+    } else if debug {
+        //Frames only count while the debugger lets the machine run
+        let mut debugger = debugger::new_debugger();
+        platform.record_history();
+        let mut frame = 0;
+        while frame < frames {
+            if debugger.update(&mut platform) {
+                frame += 1;
+            } else if debugger.paused() && debugger.detached() {
+                break;
+            } else {
+                std::thread::sleep(std::time::Duration::from_millis(16));
+            }
+        }
+    } else {
+        for frame in 0..frames {
+            if let Some(script) = &script {
+                script.apply(frame, &mut platform);
+            }
+            platform.c8_cycle();
+        }
+    }
+
+    for row in platform.chip().gfx.chunks(VIDEO_WIDTH as usize) {
+        let line: String = row.iter().map(|p| if *p != 0 { '#' } else { '.' }).collect();
+        println!("{}", line);
+    }
+    if let (Some(path), Some(map)) = (heatmap, platform.heatmap()) {
+        match map.write_png(&platform.chip().mem, path) {
+            Ok(()) => println!("Wrote the memory heatmap to {}", path),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    if let Some(path) = matches.subcommand_matches("headless").and_then(|sub| sub.get_one::<String>("screenshot")) {
+        match platform.screenshot(path) {
+            Ok(()) => println!("Wrote a screenshot to {}", path),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    finish_platform(&mut platform, matches);
+}
+
+//The terminal frontend; a debug adapter can only be served over TCP as the terminal takes stdin
+fn run_terminal(rom: &str, options: cli::Options, config: config::Config, braille: bool, matches: &clap::ArgMatches) {
+    if matches.get_one::<String>("dap").map(String::as_str) == Some("stdio") {
+        eprintln!("The terminal frontend serves the debug adapter on a TCP port only");
+        std::process::exit(1);
+    }
+    let dap = open_dap(matches);
+    let mut platform = open_platform(rom, options, config, matches);
+    let title = metadata::load_info(Path::new(rom)).title;
+    if let Err(e) = terminal::run(&mut platform, &title, braille, dap) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    finish_platform(&mut platform, matches);
+}
+
+//The symbols given on the command line or the ones next to the rom
+fn load_symbols(rom: &str, symbol_file: Option<&String>) -> symbols::Symbols {
+    match symbol_file.map(PathBuf::from).or_else(|| symbols::find(Path::new(rom))) {
+        Some(file) => symbols::load(&file).unwrap_or_else(|e| {
+            eprintln!("Ignoring symbols {}", e);
+            symbols::Symbols::default()
+        }),
+        None => symbols::Symbols::default(),
+    }
+}
+
+fn disassemble(rom: &str, symbol_file: Option<&String>) {
+    let data = std::fs::read(rom).expect("Error opening rom");
+    let symbols = load_symbols(rom, symbol_file);
+    for line in disasm::listing(&data, 0x200, &symbols) {
+        println!("{}", line);
+    }
+}
+
+fn assemble(source: &str, output: Option<&String>, map: bool) {
+    let output = match output {
+        Some(output) => output.clone(),
+        None => Path::new(source).with_extension("ch8").display().to_string(),
+    };
+    let text = std::fs::read_to_string(source).expect("Error opening source");
+    match asm::assemble(&text) {
+        Ok(program) => {
+            std::fs::write(&output, &program.bytes).expect("Error writing rom");
+            println!("Wrote {} bytes and {} labels to {}", program.bytes.len(), program.labels.len(), output);
+            if map {
+                //The source is named relative to the symbol file when they share a directory
+                let map_path = Path::new(&output).with_extension("sym");
+                let canonical_dir = |p: &Path| p.canonicalize().ok().and_then(|p| p.parent().map(Path::to_path_buf));
+                let source_path = Path::new(source);
+                let name = match (canonical_dir(source_path), canonical_dir(Path::new(&output))) {
+                    (Some(a), Some(b)) if a == b => PathBuf::from(source_path.file_name().unwrap_or_default()),
+                    _ => source_path.canonicalize().unwrap_or_else(|_| source_path.to_path_buf()),
+                };
+                let map_text = symbols::from_program(&program, &name).to_map();
+                std::fs::write(&map_path, map_text).expect("Error writing symbols");
+                println!("Wrote symbols to {}", map_path.display());
+            }
+        }
+        Err(e) => {
+            eprintln!("{}: {}", source, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn open_dap(matches: &clap::ArgMatches) -> Option<Dap> {
+    let endpoint = matches.get_one::<String>("dap")?;
+    match dap::new_dap(endpoint) {
+        Ok(dap) => Some(dap),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn open_tracer(matches: &clap::ArgMatches) -> Option<trace::Tracer> {
+    let path = matches.get_one::<String>("trace")?;
+    let format = matches.get_one::<String>("trace-format").unwrap();
+    match trace::new_tracer(path, format, matches.get_one::<usize>("trace-ring").copied()) {
+        Ok(tracer) => Some(tracer),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn start_recording(platform: &mut Platform, matches: &clap::ArgMatches) {
+    if let Some(path) = matches.get_one::<String>("record") {
+        if let Err(e) = platform.start_recording(path) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn stop_recording(platform: &mut Platform) {
+    if let Some(message) = platform.stop_recording() {
+        println!("{}", message);
+    }
+}
+
+fn profiling(matches: &clap::ArgMatches) -> bool {
+    matches.is_present("profile") || matches.is_present("flamegraph") || matches.is_present("callgrind")
+}
+
+//Prints and writes the profile of the last rom as asked on the command line
+fn write_profile(platform: &Platform, matches: &clap::ArgMatches) {
+    let profiler = match platform.profiler() {
+        Some(profiler) if profiler.total() > 0 => profiler,
+        _ => return,
+    };
+    if matches.is_present("profile") {
+        println!("{}", profiler.report(&platform.chip().mem, platform.symbols()));
+    }
+    if let Some(path) = matches.get_one::<String>("flamegraph") {
+        match std::fs::write(path, profiler.collapsed(platform.symbols())) {
+            Ok(()) => println!("Wrote collapsed stacks to {}", path),
+            Err(e) => eprintln!("{}: {}", path, e),
+        }
+    }
+    if let Some(path) = matches.get_one::<String>("callgrind") {
+        match std::fs::write(path, profiler.callgrind(platform.symbols())) {
+            Ok(()) => println!("Wrote callgrind profile to {}", path),
+            Err(e) => eprintln!("{}: {}", path, e),
+        }
+    }
+}
+
+fn save_coverage(platform: &Platform, matches: &clap::ArgMatches) {
+    if let (Some(path), Some(coverage)) = (matches.get_one::<String>("coverage"), platform.coverage()) {
+        match coverage.save(path) {
+            Ok(()) => println!("Added the coverage to {}", path),
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+}
+
+fn coverage_report<'a>(rom: &str, data_files: impl Iterator<Item = &'a String>, lcov: Option<&String>, symbol_file: Option<&String>) {
+    let data = std::fs::read(rom).expect("Error opening rom");
+    let mut total = coverage::Coverage::default();
+    total.start(&romdb::sha1_hex(&data));
+    for path in data_files {
+        if let Err(e) = coverage::load(path).and_then(|c| total.merge(&c).map_err(|e| format!("{}: {}", path, e))) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    let symbols = load_symbols(rom, symbol_file);
+    print!("{}", total.annotate(&data, &symbols));
+    if let Some(path) = lcov {
+        match total.lcov(&symbols).and_then(|text| std::fs::write(path, text).map_err(|e| format!("{}: {}", path, e))) {
+            Ok(()) => println!("Wrote {}", path),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn trace_diff(a: &str, b: &str, context: Option<&usize>) {
+    match trace::diff(a, b, context.copied().unwrap_or(trace::DEFAULT_CONTEXT)) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
         }
     }
 }
 
 fn main() {
+    let matches = cli::build().get_matches();
+    let mut options = cli::options(&matches);
+    let config = match config::load(matches.get_one::<String>("config").map(Path::new)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
 
-    // let matches = clap::App::new("Rust_8")
-    //     .about("A chip8 emulator implemented in rust")
-    //     .arg(dbg!(clap::Arg::with_name("updates"))
+    let mut rom = matches.get_one::<String>("ROM").cloned();
+    match matches.subcommand() {
+        Some(("info", sub)) => return print_info(sub.get_one::<String>("ROM").unwrap()),
+        Some(("disasm", sub)) => return disassemble(sub.get_one::<String>("ROM").unwrap(), options.symbols.as_ref()),
+        Some(("asm", sub)) => return assemble(sub.get_one::<String>("SOURCE").unwrap(), sub.get_one::<String>("output"), sub.is_present("map")),
+        Some(("coverage", sub)) => return coverage_report(sub.get_one::<String>("ROM").unwrap(), sub.get_many::<String>("DATA").unwrap(), sub.get_one::<String>("lcov"), options.symbols.as_ref()),
+        Some(("tracediff", sub)) => return trace_diff(sub.get_one::<String>("A").unwrap(), sub.get_one::<String>("B").unwrap(), sub.get_one::<usize>("context")),
+        Some(("headless", sub)) => {
+            let frames = *sub.get_one::<u32>("frames").unwrap();
+            let dap = open_dap(&matches);
+            return run_headless(sub.get_one::<String>("ROM").unwrap(), options, config, frames, matches.is_present("debug"), dap, &matches);
+        }
+        Some(("term", sub)) => return run_terminal(sub.get_one::<String>("ROM").unwrap(), options, config, sub.is_present("braille"), &matches),
+        Some(("run", sub)) => {
+            rom = sub.get_one::<String>("ROM").cloned().or(rom);
+            options.merge(&cli::window_options(sub));
+        }
+        _ => {}
+    }
+
+    //Window settings can't change per rom, only the global config applies to them
+    let mut window_options = options.clone();
+    window_options.merge(&config.defaults);
+    let scale = match window_options.scale.map(cli::check_scale) {
+        Some(Ok(scale)) => scale,
+        Some(Err(e)) => {
+            println!("Ignoring the scale of the config: {}", e);
+            DEFAULT_SCALE
+        }
+        None => DEFAULT_SCALE,
+    };
+    let rom_dir = window_options.rom_dir.unwrap_or_else(|| "games".to_string());
+
+    //Before the window opens, a TCP client might take a while to connect
+    let dap = open_dap(&matches);
 
     // Change this to OpenGL::V2_1 if not working.
     let opengl = OpenGL::V3_2;
 
     // Create an Glutin window.
-    let mut window: Window = WindowSettings::new("spinning-square", [SCALE * VIDEO_WIDTH, SCALE * VIDEO_HEIGHT])
+    let mut window: Window = WindowSettings::new("rust_8", [scale * VIDEO_WIDTH, scale * VIDEO_HEIGHT])
         .graphics_api(opengl)
-        .exit_on_esc(true)
+        .exit_on_esc(false)
+        .fullscreen(window_options.fullscreen.unwrap_or(false))
+        .resizable(true)
         .build()
         .unwrap();
 
     // Create a new game and run it.
     let mut app = App {
         gl: GlGraphics::new(opengl),
-        rotation: 0.0,
-        platform: platform::new_platform()
+        platform: platform::new_platform(),
+        library: library::scan(Path::new(&rom_dir)),
+        info: None,
+        show_info: false,
+        show_heatmap: false,
+        viewer: None,
+        rebind: None,
+        keypad: if window_options.keypad == Some(true) { Some(Keypad::default()) } else { None },
+        window_size: [scale * VIDEO_WIDTH, scale * VIDEO_HEIGHT],
+        mouse: [0.0, 0.0],
+        quit: false,
+        scale,
+        resolution: [VIDEO_WIDTH as usize, VIDEO_HEIGHT as usize],
+        toggle_fullscreen: false,
+        screen: screen::new_screen([VIDEO_WIDTH as usize, VIDEO_HEIGHT as usize], window_options.scaling.as_deref() == Some("smooth")),
+        redraw: true,
+        osd: osd::new_osd(),
+        debugger: if matches.is_present("debug") { Some(debugger::new_debugger()) } else { None },
+        dap,
     };
-    app.platform.open_rom("games/Airplane.ch8");
+    if app.debugger.is_some() {
+        app.platform.record_history();
+    }
+    if let Some(tracer) = open_tracer(&matches) {
+        app.platform.set_tracer(tracer);
+    }
+    if profiling(&matches) {
+        app.platform.enable_profiler();
+    }
+    if matches.is_present("coverage") {
+        app.platform.enable_coverage();
+    }
+    app.platform.set_options(options);
+    app.platform.set_config(config);
+    if let Some(rom) = rom {
+        app.launch(Path::new(&rom));
+    }
+    start_recording(&mut app.platform, &matches);
 
-    let mut settings = EventSettings::new();
+    //One update per 60Hz frame, the platform runs the rom's tick rate of instructions in each
+    let settings = EventSettings::new().ups(60).swap_buffers(false);
     let mut events = Events::new(settings);
+    let mut title = String::new();
     while let Some(e) = events.next(&mut window) {
-
         //Receive input
         if let Some(btn) = e.press_args() {
             app.set_input(btn, true);
@@ -114,17 +839,54 @@ fn main() {
         if let Some(btn) = e.release_args() {
             app.set_input(btn, false);
         };
+        if let Some(position) = e.mouse_cursor_args() {
+            app.move_mouse(position);
+        }
+        if let Some(args) = e.touch_args() {
+            app.touch(&args);
+        }
+        if let Some(text) = e.text_args() {
+            if app.info.is_none() {
+                app.library.type_text(&text);
+            }
+        }
+        if app.quit {
+            window.set_should_close(true);
+        }
+        if app.toggle_fullscreen {
+            app.toggle_fullscreen = false;
+            let glutin_window = window.ctx.window();
+            let fullscreen = match glutin_window.fullscreen() {
+                Some(_) => None,
+                None => Some(Fullscreen::Borderless(glutin_window.current_monitor())),
+            };
+            glutin_window.set_fullscreen(fullscreen);
+        }
+        //A machine switching its resolution gets a window of the same scale
+        let resolution = app.platform.resolution();
+        if resolution != app.resolution {
+            app.resolution = resolution;
+            if window.ctx.window().fullscreen().is_none() {
+                window.set_size([app.scale * resolution[0] as f64, app.scale * resolution[1] as f64]);
+            }
+        }
+        if app.title() != title {
+            title = app.title();
+            window.set_title(title.clone());
+        }
         //update chip8
         if let Some(args) = e.update_args() {
             app.update(&args);
         }
-        //Render the gfx
+        if e.focus_args().is_some() {
+            app.redraw = true;
+        }
+        //Render the gfx, the buffers are only swapped after something was drawn
         if let Some(args) = e.render_args() {
-            app.render(&args);
+            if app.render(&args) {
+                window.swap_buffers();
+            }
         }
     }
-
-
-
-
+    finish_platform(&mut app.platform, &matches);
 }
@@ -0,0 +1,285 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+//Words that make a sentence of a game doc likely to describe the controls
+const KEY_WORDS: [&str; 8] = ["key", "button", "press", "move", "shoot", "rotate", "drop", "fire"];
+
+pub struct RomInfo {
+    pub title: String,
+    pub author: Option<String>,
+    pub year: Option<u16>,
+    pub description: Option<String>,
+    //Sentences of the doc describing the controls
+    pub keys: Vec<String>,
+    //The companion .txt the doc fields were taken from
+    pub doc_path: Option<PathBuf>,
+}
+
+impl RomInfo {
+    //Text lines for the info overlay and `rust_8 info`
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![format!("Title:  {}", self.title)];
+        if let Some(author) = &self.author {
+            lines.push(format!("Author: {}", author));
+        }
+        if let Some(year) = self.year {
+            lines.push(format!("Year:   {}", year));
+        }
+        if let Some(doc) = &self.doc_path {
+            lines.push(format!("Doc:    {}", doc.display()));
+        }
+        if !self.keys.is_empty() {
+            lines.push(String::new());
+            lines.push("Keys:".to_string());
+            for k in &self.keys {
+                lines.push(format!("  {}", k));
+            }
+        }
+        if let Some(description) = &self.description {
+            lines.push(String::new());
+            lines.push("Description:".to_string());
+            for l in description.lines() {
+                lines.push(format!("  {}", l));
+            }
+        }
+        lines
+    }
+}
+
+//Builds the metadata of a rom from its file name and the companion .txt next to it, if there is one
+pub fn load_info(rom_path: &Path) -> RomInfo {
+    let stem = rom_path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let (title, author, year) = parse_filename(&stem);
+    let mut info = RomInfo {
+        title,
+        author,
+        year,
+        description: None,
+        keys: Vec::new(),
+        doc_path: None,
+    };
+
+    if let Some(doc_path) = find_doc(rom_path) {
+        if let Ok(bytes) = fs::read(&doc_path) {
+            parse_doc(&String::from_utf8_lossy(&bytes), &mut info);
+            info.doc_path = Some(doc_path);
+        }
+    }
+    info
+}
+
+//Splits the "Title [Author, Year] (alt)" naming convention of the rom collection.
+//Older dumps use parentheses instead of brackets: "Title (Author, Year)".
+pub fn parse_filename(stem: &str) -> (String, Option<String>, Option<u16>) {
+    if let (Some(open), Some(close)) = (stem.find('['), stem.find(']')) {
+        if open < close {
+            let (author, year) = parse_credits(&stem[open + 1..close]);
+            return (stem[..open].trim().to_string(), author, year);
+        }
+    }
+
+    //Only treat a trailing parenthesized group as credits if it carries a year, "Pong (1 player)" is part of the title
+    if let (Some(open), true) = (stem.rfind('('), stem.trim_end().ends_with(')')) {
+        let (author, year) = parse_credits(&stem[open + 1..stem.trim_end().len() - 1]);
+        if year.is_some() {
+            return (stem[..open].trim().to_string(), author, year);
+        }
+    }
+    (stem.trim().to_string(), None, None)
+}
+
+fn parse_credits(credits: &str) -> (Option<String>, Option<u16>) {
+    let mut parts: Vec<&str> = credits.split(',').map(|p| p.trim()).collect();
+    let year = parts.last().and_then(|p| parse_year(p));
+    if year.is_some() {
+        parts.pop();
+    }
+    let author = parts.join(", ");
+    if author.is_empty() {
+        (None, year)
+    } else {
+        (Some(author), year)
+    }
+}
+
+fn parse_year(text: &str) -> Option<u16> {
+    text.split(|c: char| !c.is_ascii_digit())
+        .filter(|p| p.len() == 4)
+        .filter_map(|p| p.parse::<u16>().ok())
+        .find(|y| (1970..2100).contains(y))
+}
+
+//The doc shares the rom's file name; alternate versions ("Title (alt).ch8") share the doc of the original
+fn find_doc(rom_path: &Path) -> Option<PathBuf> {
+    let mut stem = rom_path.file_stem()?.to_string_lossy().trim().to_string();
+    loop {
+        let candidate = rom_path.with_file_name(format!("{}.txt", stem));
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        if !stem.ends_with(')') {
+            return None;
+        }
+        let open = stem.rfind('(')?;
+        //Older dumps put the credits in parentheses while their doc uses brackets
+        let bracketed = rom_path.with_file_name(format!("{}[{}].txt", &stem[..open], &stem[open + 1..stem.len() - 1]));
+        if bracketed.is_file() {
+            return Some(bracketed);
+        }
+        stem = stem[..open].trim().to_string();
+    }
+}
+
+fn parse_doc(text: &str, info: &mut RomInfo) {
+    let lines: Vec<&str> = text.lines().collect();
+
+    //Header fields in the "Title : Astro Dodge" style
+    for line in &lines {
+        if let Some((key, value)) = line.split_once(':') {
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            match key.trim().to_lowercase().as_str() {
+                "title" => info.title = value.to_string(),
+                "author" if info.author.is_none() => info.author = Some(value.to_string()),
+                "date" | "year" if info.year.is_none() => info.year = parse_year(value),
+                _ => {}
+            }
+        }
+    }
+
+    //Centered "by Fran Dachille" credit line below the title
+    if info.author.is_none() {
+        info.author = lines.iter().take(5)
+            .map(|l| l.trim())
+            .find(|l| l.to_lowercase().starts_with("by "))
+            .map(|l| l[3..].trim().to_string());
+    }
+    if info.year.is_none() {
+        info.year = lines.iter().take(5).find_map(|l| parse_year(l));
+    }
+
+    let description = description_text(&lines);
+    info.keys = split_sentences(&description).into_iter()
+        .filter(|s| {
+            let lower = s.to_lowercase();
+            KEY_WORDS.iter().any(|w| lower.contains(w))
+        })
+        .collect();
+    if !description.is_empty() {
+        info.description = Some(description);
+    }
+}
+
+//The text under a "Description:" heading, or the whole doc if there is no such heading
+fn description_text(lines: &[&str]) -> String {
+    let start = lines.iter().position(|l| l.trim().eq_ignore_ascii_case("description:"));
+    let body: Vec<&str> = match start {
+        Some(start) => {
+            let mut body = Vec::new();
+            for (i, line) in lines.iter().enumerate().skip(start + 1) {
+                //Headings are underlined with dashes; stop at the next one
+                let underlined = lines.get(i + 1).is_some_and(|n| is_rule(n));
+                if underlined && !line.trim().is_empty() {
+                    break;
+                }
+                body.push(*line);
+            }
+            body
+        }
+        None => lines.to_vec(),
+    };
+
+    //Join wrapped lines into paragraphs, dropping rules and banner art
+    let mut paragraphs: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for line in body {
+        let line = line.trim();
+        if line.is_empty() || is_rule(line) || !line.chars().any(|c| c.is_alphanumeric()) {
+            if !current.is_empty() {
+                paragraphs.push(current.clone());
+                current.clear();
+            }
+            continue;
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(&line.split_whitespace().collect::<Vec<&str>>().join(" "));
+    }
+    if !current.is_empty() {
+        paragraphs.push(current);
+    }
+    paragraphs.join("\n")
+}
+
+fn is_rule(line: &str) -> bool {
+    let line = line.trim();
+    line.len() >= 3 && line.chars().all(|c| c == '-' || c == '=')
+}
+
+fn split_sentences(text: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    let mut current = String::new();
+    let chars: Vec<char> = text.chars().collect();
+    for (i, c) in chars.iter().enumerate() {
+        current.push(*c);
+        //"PONG version 1.1" is not the end of a sentence
+        let next = chars.get(i + 1).copied().unwrap_or(' ');
+        if matches!(c, '.' | '!' | '?' | '\n') && next.is_whitespace() {
+            let sentence = current.trim().to_string();
+            if !sentence.is_empty() {
+                sentences.push(sentence);
+            }
+            current.clear();
+        }
+    }
+    let rest = current.trim();
+    if !rest.is_empty() {
+        sentences.push(rest.to_string());
+    }
+    sentences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("games").join(name)
+    }
+
+    #[test]
+    fn filename_with_bracketed_credits() {
+        let (title, author, year) = parse_filename("Astro Dodge [Revival Studios, 2008]");
+        assert_eq!(title, "Astro Dodge");
+        assert_eq!(author.as_deref(), Some("Revival Studios"));
+        assert_eq!(year, Some(2008));
+    }
+
+    #[test]
+    fn filename_with_parenthesized_credits() {
+        let (title, author, year) = parse_filename("Lunar Lander (Udo Pernisz, 1979)");
+        assert_eq!(title, "Lunar Lander");
+        assert_eq!(author.as_deref(), Some("Udo Pernisz"));
+        assert_eq!(year, Some(1979));
+
+        //Without a year the parentheses are part of the title
+        assert_eq!(parse_filename("Pong (1 player)"), ("Pong (1 player)".to_string(), None, None));
+    }
+
+    #[test]
+    fn credits_without_author_or_year() {
+        assert_eq!(parse_credits("Roger Ivie"), (Some("Roger Ivie".to_string()), None));
+        assert_eq!(parse_credits("1990"), (None, Some(1990)));
+    }
+
+    #[test]
+    fn doc_of_the_rom() {
+        assert_eq!(find_doc(&game("Astro Dodge [Revival Studios, 2008].ch8")), Some(game("Astro Dodge [Revival Studios, 2008].txt")));
+        assert_eq!(find_doc(&game("15 Puzzle [Roger Ivie] (alt).ch8")), Some(game("15 Puzzle [Roger Ivie].txt")));
+        assert_eq!(find_doc(&game("Lunar Lander (Udo Pernisz, 1979).ch8")), Some(game("Lunar Lander [Udo Pernisz, 1979].txt")));
+        assert_eq!(find_doc(&game("Airplane.ch8")), None);
+    }
+}
@@ -0,0 +1,155 @@
+use std::time::{Duration, Instant};
+
+use graphics::{rectangle, Context, Graphics};
+
+use crate::font;
+use crate::keypad;
+
+const PX: f64 = 2.0;
+const MARGIN: f64 = 8.0;
+const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+const TEXT: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+const PAUSED: [f32; 4] = [1.0, 0.8, 0.2, 1.0];
+const RECORDING: [f32; 4] = [0.95, 0.2, 0.2, 1.0];
+const KEY: [f32; 4] = [0.25, 0.25, 0.28, 0.8];
+const KEY_POLLED: [f32; 4] = [0.2, 0.4, 0.75, 0.9];
+//a key the game checked during the last frames
+const KEY_PRESSED: [f32; 4] = [0.95, 0.95, 0.95, 1.0];
+const KEY_TEXT: [f32; 4] = [0.05, 0.05, 0.05, 1.0];
+const KEY_SIZE: f64 = 14.0;
+const MESSAGE_TIME: Duration = Duration::from_secs(3);
+const MAX_MESSAGES: usize = 4;
+//How often the measurements are updated
+const MEASURE_TIME: Duration = Duration::from_millis(500);
+
+//The state of the machine the OSD shows, gathered by the frontend when it draws
+pub struct Status {
+    pub paused: bool,
+    pub recording: bool,
+    pub pressed: [bool; 16],
+    pub polled: [bool; 16],
+    pub ips: u32,
+    //instructions per second the rom is set to run at
+    pub keypad: bool,
+    //the clickable keypad is shown in place of the OSD's
+}
+
+//On-screen display over the game: the measured frame rate, instructions per second and speed, the pause
+//and recording indicators and the keypad on demand, and messages that disappear after a few seconds
+pub struct Osd {
+    visible: bool,
+    //the measurements and the keypad, messages show either way
+    messages: Vec<(String, Instant)>,
+    since: Instant,
+    frames: u32,
+    cycles: u64,
+    //start of the current measurement
+    fps: f64,
+    ips: f64,
+}
+
+impl Osd {
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+        self.since = Instant::now();
+        self.frames = 0;
+    }
+
+    pub fn message(&mut self, text: &str) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.remove(0);
+        }
+        self.messages.push((text.to_string(), Instant::now()));
+    }
+
+    //Whether something is shown that changes over time, so that every frame has to be drawn
+    pub fn active(&self) -> bool {
+        self.visible || !self.messages.is_empty()
+    }
+
+    //Counts a drawn frame, takes the measurements and drops the messages that were shown long enough
+    pub fn frame(&mut self, cycles: u64) {
+        let now = Instant::now();
+        self.messages.retain(|(_, shown)| now - *shown < MESSAGE_TIME);
+        if self.frames == 0 {
+            self.cycles = cycles;
+        }
+        self.frames += 1;
+        let elapsed = now - self.since;
+        if elapsed >= MEASURE_TIME {
+            self.fps = (self.frames - 1) as f64 / elapsed.as_secs_f64();
+            self.ips = cycles.saturating_sub(self.cycles) as f64 / elapsed.as_secs_f64();
+            self.since = now;
+            self.frames = 0;
+        }
+    }
+
+    pub fn draw<G: Graphics>(&self, status: &Status, window_size: [f64; 2], c: &Context, gl: &mut G) {
+        let row_height = font::CELL_HEIGHT * PX;
+        let label = |text: &str, x: f64, y: f64, color: [f32; 4], gl: &mut G| {
+            let width = text.len() as f64 * font::CELL_WIDTH * PX;
+            rectangle(BACKGROUND, [x - PX, y - PX, width + PX, row_height + PX], c.transform, gl);
+            font::draw_text(text, x, y, PX, color, c.transform, gl);
+        };
+
+        //Indicators at the top right
+        let mut right = window_size[0] - MARGIN;
+        for (shown, text, color) in [(status.recording, "REC", RECORDING), (status.paused, "PAUSED", PAUSED)] {
+            if shown {
+                right -= text.len() as f64 * font::CELL_WIDTH * PX;
+                label(text, right, MARGIN, color, gl);
+                right -= 2.0 * font::CELL_WIDTH * PX;
+            }
+        }
+
+        //Messages at the bottom left, the newest at the bottom
+        for (n, (text, _)) in self.messages.iter().rev().enumerate() {
+            let y = window_size[1] - MARGIN - (n + 1) as f64 * (row_height + PX);
+            label(text, MARGIN, y, TEXT, gl);
+        }
+
+        if !self.visible {
+            return;
+        }
+        let speed = if status.ips > 0 { self.ips * 100.0 / status.ips as f64 } else { 0.0 };
+        label(&format!("{:.0} FPS", self.fps), MARGIN, MARGIN, TEXT, gl);
+        label(&format!("{:.0} IPS", self.ips), MARGIN, MARGIN + row_height + PX, TEXT, gl);
+        label(&format!("SPEED {:.0}%", speed), MARGIN, MARGIN + 2.0 * (row_height + PX), TEXT, gl);
+
+        //The keypad at the bottom right, pressed keys light up and the ones the game checks are blue
+        if status.keypad {
+            return;
+        }
+        let left = window_size[0] - MARGIN - 4.0 * (KEY_SIZE + PX);
+        let top = window_size[1] - MARGIN - 4.0 * (KEY_SIZE + PX);
+        for (row, keys) in keypad::LAYOUT.iter().enumerate() {
+            for (column, key) in keys.iter().enumerate() {
+                let x = left + column as f64 * (KEY_SIZE + PX);
+                let y = top + row as f64 * (KEY_SIZE + PX);
+                let (color, text) = if status.pressed[*key as usize] {
+                    (KEY_PRESSED, KEY_TEXT)
+                } else if status.polled[*key as usize] {
+                    (KEY_POLLED, TEXT)
+                } else {
+                    (KEY, TEXT)
+                };
+                rectangle(color, [x, y, KEY_SIZE, KEY_SIZE], c.transform, gl);
+                let glyph = [font::GLYPH_WIDTH * PX, font::GLYPH_HEIGHT * PX];
+                font::draw_text(&format!("{:X}", key), x + (KEY_SIZE - glyph[0]) / 2.0, y + (KEY_SIZE - glyph[1]) / 2.0,
+                    PX, text, c.transform, gl);
+            }
+        }
+    }
+}
+
+pub fn new_osd() -> Osd {
+    Osd {
+        visible: false,
+        messages: Vec::new(),
+        since: Instant::now(),
+        frames: 0,
+        cycles: 0,
+        fps: 0.0,
+        ips: 0.0,
+    }
+}
@@ -0,0 +1,110 @@
+use serde::{Deserialize, Deserializer, Serializer};
+
+//Background, plane 1, plane 2 and both planes as opengl colors. Monochrome roms only use the first two.
+pub type Palette = [[f32; 4]; 4];
+
+pub const NAMES: [&str; 5] = ["classic", "amber", "green", "octo", "lcd"];
+pub const DEFAULT: &str = "classic";
+
+fn named(name: &str) -> Option<[&'static str; 4]> {
+    match name.to_lowercase().as_str() {
+        "classic" => Some(["#000000", "#ffffff", "#aaaaaa", "#555555"]),
+        "amber" => Some(["#1a0f00", "#ffb000", "#b36b00", "#5c3500"]),
+        //green phosphor
+        "green" => Some(["#051405", "#33ff66", "#1f9940", "#0f4d20"]),
+        //the default colors of Octo
+        "octo" => Some(["#996600", "#ffcc00", "#ff6600", "#662200"]),
+        "lcd" => Some(["#9bbc0f", "#0f380f", "#306230", "#8bac0f"]),
+        _ => None,
+    }
+}
+
+//Parses "#rrggbb" into an opengl color
+pub fn parse_color(hex: &str) -> Option<[f32; 4]> {
+    let hex = hex.trim().trim_start_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    let channel = |shift: u32| ((value >> shift) & 0xff) as f32 / 255.0;
+    Some([channel(16), channel(8), channel(0), 1.0])
+}
+
+pub fn to_hex(color: [f32; 4]) -> String {
+    let channel = |c: f32| (c * 255.0).round() as u8;
+    format!("#{:02x}{:02x}{:02x}", channel(color[0]), channel(color[1]), channel(color[2]))
+}
+
+fn parse_colors(colors: &[&str]) -> Option<Palette> {
+    match colors {
+        [background, foreground] => {
+            let foreground = parse_color(foreground)?;
+            Some([parse_color(background)?, foreground, foreground, foreground])
+        }
+        [background, plane1, plane2, both] => {
+            Some([parse_color(background)?, parse_color(plane1)?, parse_color(plane2)?, parse_color(both)?])
+        }
+        _ => None,
+    }
+}
+
+//A palette name, or the background and foreground colors, or the four colors of the XO-CHIP planes
+pub fn parse(setting: &[String]) -> Result<Palette, String> {
+    let colors: Vec<&str> = match setting {
+        [name] if named(name).is_some() => named(name).unwrap().to_vec(),
+        _ => setting.iter().map(|c| c.as_str()).collect(),
+    };
+    parse_colors(&colors).ok_or_else(|| {
+        format!("Invalid palette {}, expected one of {} or 2 or 4 colors like #000000", setting.join(","), NAMES.join(", "))
+    })
+}
+
+pub fn default_palette() -> Palette {
+    parse(&[DEFAULT.to_string()]).unwrap()
+}
+
+//The color of a framebuffer pixel; pixels fading out with the decay mode get a mix of the two colors
+pub fn shade(palette: &Palette, pixel: u8) -> [f32; 4] {
+    let share = pixel as f32 / 255.0;
+    let mut color = palette[0];
+    for (channel, lit) in color.iter_mut().zip(palette[1].iter()) {
+        *channel += (lit - *channel) * share;
+    }
+    color
+}
+
+//The named palette after the given setting, for cycling through them
+pub fn next(setting: Option<&[String]>) -> &'static str {
+    let current = match setting {
+        Some([name]) => NAMES.iter().position(|n| n.eq_ignore_ascii_case(name)),
+        _ => None,
+    };
+    match current {
+        Some(n) => NAMES[(n + 1) % NAMES.len()],
+        None => NAMES[0],
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Setting {
+    One(String),
+    Many(Vec<String>),
+}
+
+//Reads palette = "amber", palette = "#000000,#ff8c00" or palette = ["#000000", "#ff8c00"]
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<String>>, D::Error> {
+    Ok(Some(match Setting::deserialize(deserializer)? {
+        Setting::One(text) => text.split(',').map(|c| c.trim().to_string()).collect(),
+        Setting::Many(colors) => colors,
+    }))
+}
+
+//Names are written as a string, colors as a list
+pub fn serialize<S: Serializer>(setting: &Option<Vec<String>>, serializer: S) -> Result<S::Ok, S::Error> {
+    match setting.as_deref() {
+        Some([name]) => serializer.serialize_str(name),
+        Some(colors) => serializer.collect_seq(colors),
+        None => serializer.serialize_none(),
+    }
+}
@@ -1,76 +1,692 @@
 use std::fs;
-use std::thread;
-use std::time::Duration;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
-use crate::chip8::{Chip8, new_chip8};
+use piston::Key;
+
+use crate::chip8::{Chip8, Fault, Quirks, new_chip8};
+use crate::cli::Options;
+use crate::config::Config;
+use crate::coverage::Coverage;
+use crate::disasm;
+use crate::flicker::{self, Display, Mode};
+use crate::heatmap::{self, Heatmap};
+use crate::image;
+use crate::history::{Event, History, Snapshot};
+use crate::keymap::{self, Keymap};
+use crate::palette::{self, Palette};
+use crate::profile::{self, Profiler};
+use crate::record::{self, Recorder};
+use crate::romdb::{self, RomDb};
+use crate::symbols::{self, Symbols};
+use crate::trace::Tracer;
+
+//Instructions per 60Hz frame when the rom database has no recommendation
+const DEFAULT_TICK_RATE: u32 = 10;
+//Frames a key counts as checked by the rom after it was last polled with EX9E, EXA1 or FX0A
+const POLL_FRAMES: u64 = 10;
 
 pub struct Platform {
     chip: Chip8,
-    clock: u8,
+    db: RomDb,
+    ips: u32,
+    //instructions executed per second
+    palette: Option<Palette>,
+    //background and foreground color recommended for the rom
+    keymap: Keymap,
+    //the preset with the rom's keys from the database, or the keys of the config
+    rom_keys: Keymap,
+    //keys the rom database recommends for the rom
+    options: Options,
+    //command line options, they take precedence over the config
+    config: Config,
+    settings: Options,
+    //options in effect for the running rom
+    rom_hash: Option<String>,
+    rom_name: String,
+    //file name of the rom without its extension, for naming screenshots
+    beeping: bool,
+    frame_cycles: u32,
+    //instructions executed in the current frame
+    carry: u32,
+    //sixtieths of an instruction the frames so far fell short of ips, they make the next frames longer
+    fault: Option<Fault>,
+    //the machine is halted after a fault until the next rom is opened
+    cycles: u64,
+    //instructions executed since the rom was opened
+    history: Option<History>,
+    //recorded for the debugger's reverse execution
+    edited: bool,
+    //the debugger changed the machine, the next instruction takes a snapshot
+    replaying: bool,
+    symbols: Symbols,
+    //labels and source lines of the running rom
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    //restarts with every rom
+    heatmap: Option<Heatmap>,
+    coverage: Option<Coverage>,
+    display: Display,
+    //what the frontend shows, see the anti-flicker modes
+    recorder: Option<Recorder>,
+    polled: [Option<u64>; 16],
+    //instruction count when the rom last checked each key, for the OSD
 }
 
 impl Platform {
+    #[allow(dead_code)]
     pub fn init(&mut self) {
         self.chip.init();
     }
 
+    pub fn set_options(&mut self, options: Options) {
+        self.options = options;
+    }
+
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
     pub fn open_rom(&mut self, path: &str) {
         let rom_data = fs::read(path).expect("Error opening rom");
-        self.chip.load_rom(rom_data.as_slice());
-        self.chip.print_memory();
+        self.open_rom_data(path, rom_data);
     }
 
+    //Starts a rom that is already in memory, e.g. assembled by the debug adapter; path names it
+    pub fn open_rom_data(&mut self, path: &str, rom_data: Vec<u8>) {
+        let hash = romdb::sha1_hex(&rom_data);
+        let file_name = Path::new(path).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+
+        self.chip = new_chip8();
+        self.frame_cycles = 0;
+        self.carry = 0;
+        self.fault = None;
+        self.cycles = 0;
+        if self.history.is_some() {
+            self.history = Some(History::default());
+        }
+        if self.profiler.is_some() {
+            self.profiler = Some(profile::new_profiler(path));
+        }
+        if self.heatmap.is_some() {
+            self.heatmap = Some(heatmap::new_heatmap());
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.start(&hash);
+        }
+        self.ips = DEFAULT_TICK_RATE * 60;
+        self.palette = None;
+        self.rom_keys = Keymap::default();
+        self.display = flicker::new_display(Mode::Off);
+        self.polled = [None; 16];
+
+        let mut load_address = None;
+        if let Some(entry) = self.db.lookup(&hash) {
+            println!("Found {} ({}) in the rom database", entry.title.as_deref().unwrap_or(path), hash);
+            if let Some(name) = &entry.quirks {
+                match Quirks::preset(name) {
+                    Some(quirks) => self.chip.quirks = quirks,
+                    None => println!("Unknown quirks preset {}", name),
+                }
+            }
+            if let Some(tick_rate) = entry.tick_rate {
+                self.ips = tick_rate.saturating_mul(60).max(1);
+            }
+            if let Some(keys) = &entry.keymap {
+                match keymap::from_names(keys) {
+                    Ok(keys) => self.rom_keys = keys,
+                    Err(e) => println!("Ignoring the keymap of the rom database: {}", e),
+                }
+            }
+            if let Some(palette) = &entry.palette {
+                match palette::parse(palette) {
+                    Ok(palette) => self.palette = Some(palette),
+                    Err(e) => println!("Ignoring the palette of the rom database: {}", e),
+                }
+            }
+            load_address = entry.load_address;
+        }
+
+        //Command line, then the rom's section of the config, then the global config
+        self.settings = self.options.clone();
+        self.settings.merge(&self.config.rom_options(&hash, &file_name));
+        self.settings.merge(&self.config.defaults);
+        self.apply_settings();
+        self.rom_hash = Some(hash);
+        self.rom_name = Path::new(path).file_stem().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+
+        match load_address {
+            Some(addr) => self.chip.load_rom_at(rom_data.as_slice(), addr),
+            None => self.chip.load_rom(rom_data.as_slice()),
+        }
+
+        let symbol_file = self.settings.symbols.as_ref().map(PathBuf::from).or_else(|| symbols::find(Path::new(path)));
+        self.symbols = Symbols::default();
+        if let Some(file) = symbol_file {
+            match symbols::load(&file) {
+                Ok(loaded) => self.symbols = loaded,
+                Err(e) => println!("Ignoring symbols {}", e),
+            }
+        }
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    fn apply_settings(&mut self) {
+        let options = &self.settings;
+        if let Some(ips) = options.ips {
+            self.ips = ips.max(1);
+        }
+        if let Some(name) = &options.quirks {
+            match Quirks::preset(name) {
+                Some(quirks) => self.chip.quirks = quirks,
+                None => println!("Unknown quirks preset {}", name),
+            }
+        }
+        if let Some(palette) = &options.palette {
+            match palette::parse(palette) {
+                Ok(palette) => self.palette = Some(palette),
+                Err(e) => println!("{}", e),
+            }
+        }
+        if let Some(mode) = &options.flicker {
+            match flicker::parse(mode) {
+                Ok(mode) => self.display.set_mode(mode),
+                Err(e) => println!("{}", e),
+            }
+        }
+        if let Some(seed) = options.seed {
+            self.chip.set_seed(seed);
+        }
+
+        //Keys of the config replace the preset and the rom database's keys
+        let preset = keymap::preset(options.keymap.as_deref().unwrap_or(keymap::DEFAULT)).unwrap_or_else(|e| {
+            println!("{}", e);
+            keymap::preset(keymap::DEFAULT).unwrap()
+        });
+        self.keymap = match options.keys.as_ref().map(keymap::from_names) {
+            Some(Ok(keys)) => keys,
+            Some(Err(e)) => {
+                println!("Ignoring the keys of the config: {}", e);
+                preset
+            }
+            None => preset,
+        };
+        if options.keys.is_none() {
+            self.keymap.extend(&self.rom_keys);
+        }
+    }
+
+    //Runs one 60Hz frame: frame_length instructions followed by a timer tick
     pub fn c8_cycle(&mut self) {
-        self.chip.cycle();
-    }
-
-    pub fn get_gfx(&mut self) -> [u8; 32*64] {
-        return self.chip.gfx;
-    }
-
-    pub fn handle_input(&mut self, code: &str, is_pressed: bool) {
-        if code == "D1" {
-            self.chip.set_key(0, is_pressed);
-        } else if code == "D2" {
-            self.chip.set_key(1, is_pressed);
-        } else if code == "D3" {
-            self.chip.set_key(2, is_pressed);
-        } else if code == "D4" {
-            self.chip.set_key(3, is_pressed);
-        } else if code == "Q" {
-            self.chip.set_key(4, is_pressed);
-        } else if code == "W" {
-            self.chip.set_key(5, is_pressed);
-        } else if code == "E" {
-            self.chip.set_key(6, is_pressed);
-        } else if code == "R" {
-            self.chip.set_key(7, is_pressed);
-        } else if code == "A" {
-            self.chip.set_key(8, is_pressed);
-        } else if code == "S" {
-            self.chip.set_key(9, is_pressed);
-        } else if code == "D" {
-            self.chip.set_key(10, is_pressed);
-        } else if code == "F" {
-            self.chip.set_key(11, is_pressed);
-        } else if code == "Y" {
-            self.chip.set_key(12, is_pressed);
-        } else if code == "X" {
-            self.chip.set_key(13, is_pressed);
-        } else if code == "C" {
-            self.chip.set_key(14, is_pressed);
-        } else if code == "V" {
-            self.chip.set_key(15, is_pressed);
-        } else {
-            println!("Error");
+        self.run_frame(|_| false);
+    }
+
+    //Runs the rest of the current frame; stop is asked before every instruction and can interrupt the frame.
+    //Returns whether the frame was interrupted, by stop or a fault.
+    pub fn run_frame<F: FnMut(&Chip8) -> bool>(&mut self, mut stop: F) -> bool {
+        loop {
+            if self.fault.is_some() || stop(&self.chip) {
+                return true;
+            }
+            if self.step() {
+                return false;
+            }
+        }
+    }
+
+    //Executes one instruction and returns whether it completed the frame
+    pub fn step(&mut self) -> bool {
+        if self.fault.is_some() {
+            return false;
+        }
+        if let Some(history) = &mut self.history {
+            if self.edited || history.needs_snapshot(self.cycles) {
+                history.record(Snapshot { cycles: self.cycles, chip: self.chip.clone(), frame_cycles: self.frame_cycles, carry: self.carry });
+                self.edited = false;
+            }
+        }
+        if !self.replaying {
+            if let Some(tracer) = &mut self.tracer {
+                tracer.trace(self.cycles, &self.chip, &self.symbols);
+            }
+        }
+        if !self.replaying {
+            if let Some(profiler) = &mut self.profiler {
+                profiler.before(&self.chip);
+            }
+            if let Some(heatmap) = &mut self.heatmap {
+                heatmap.record(&self.chip);
+            }
+        }
+        let (pc, opcode) = (self.chip.pc, disasm::fetch(&self.chip.mem, self.chip.pc as usize));
+        if let Err(fault) = self.chip.cycle() {
+            if !self.replaying {
+                println!("Machine halted: {}", fault);
+                if let Some(tracer) = &mut self.tracer {
+                    tracer.fault(&format!("Machine halted: {}", fault));
+                }
+            }
+            self.fault = Some(fault);
+            return false;
+        }
+        if !self.replaying {
+            if let Some(profiler) = &mut self.profiler {
+                profiler.after(&self.chip);
+            }
+            if let Some(coverage) = &mut self.coverage {
+                coverage.record(pc, opcode, self.chip.pc);
+            }
+        }
+        match opcode >> 12 {
+            0xd => self.display.after_draw(&self.chip.gfx, self.chip.reg[0xf] != 0),
+            0x0 if opcode == 0x00e0 => self.display.after_draw(&self.chip.gfx, false),
+            0xe if opcode & 0xff == 0x9e || opcode & 0xff == 0xa1 => {
+                let key = self.chip.reg[(opcode as usize >> 8) & 0xf] & 0xf;
+                self.polled[key as usize] = Some(self.cycles);
+            }
+            0xf if opcode & 0xff == 0x0a => self.polled = [Some(self.cycles); 16],
+            _ => {}
+        }
+        self.cycles += 1;
+        self.frame_cycles += 1;
+        if self.frame_cycles >= self.frame_length() {
+            self.end_frame();
+            return true;
+        }
+        false
+    }
+
+    //Instructions in the current frame, ips / 60 with the carry of the frames before, so that the
+    //instructions per second are met on average
+    fn frame_length(&self) -> u32 {
+        ((self.ips + self.carry) / 60).max(1)
+    }
+
+    fn end_frame(&mut self) {
+        self.carry = (self.ips + self.carry) % 60;
+        self.frame_cycles = 0;
+        self.chip.tick_timers();
+        self.display.end_frame(&self.chip.gfx);
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.decay();
+        }
+        if !self.replaying {
+            if let Some(recorder) = &mut self.recorder {
+                let palette = self.palette.unwrap_or_else(palette::default_palette);
+                if let Err(e) = recorder.frame(self.display.pixels(&self.chip.gfx), palette, self.chip.sound_active()) {
+                    println!("Recording stopped: {}", e);
+                    self.recorder = None;
+                }
+            }
+        }
+
+        //The beeper is the terminal bell, rung when the sound timer starts
+        let active = self.chip.sound_active();
+        if active && !self.beeping && !self.replaying && self.settings.mute != Some(true) {
+            print!("\x07");
+            let _ = io::stdout().flush();
+        }
+        self.beeping = active;
+    }
+
+    pub fn chip(&self) -> &Chip8 {
+        &self.chip
+    }
+
+    pub fn chip_mut(&mut self) -> &mut Chip8 {
+        self.edited = true;
+        self.chip.dirty = true;
+        &mut self.chip
+    }
+
+    pub fn set_key(&mut self, key: u8, is_pressed: bool) {
+        if let Some(history) = &mut self.history {
+            history.record_event(self.cycles, Event::Key(key, is_pressed));
+        }
+        self.chip.set_key(key, is_pressed);
+    }
+
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(profile::new_profiler(""));
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    //Memory accesses are only tracked once the heatmap is asked for
+    pub fn enable_heatmap(&mut self) {
+        if self.heatmap.is_none() {
+            self.heatmap = Some(heatmap::new_heatmap());
+        }
+    }
+
+    pub fn heatmap(&self) -> Option<&Heatmap> {
+        self.heatmap.as_ref()
+    }
+
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::default());
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    //Instructions per second the rom runs at
+    pub fn ips(&self) -> u32 {
+        self.ips
+    }
+
+    //The keys the rom checked with EX9E, EXA1 or FX0A during the last frames
+    pub fn polled_keys(&self) -> [bool; 16] {
+        let window = POLL_FRAMES * self.ips as u64 / 60;
+        self.polled.map(|at| at.is_some_and(|at| self.cycles.saturating_sub(at) < window))
+    }
+
+    pub fn record_history(&mut self) {
+        self.history = Some(History::default());
+    }
+
+    //First instruction the history can go back to
+    pub fn history_start(&self) -> Option<u64> {
+        self.history.as_ref().filter(|h| !h.is_empty()).map(|h| h.oldest())
+    }
+
+    //Brings the machine to the state before instruction number `to` by restoring the nearest snapshot
+    //at or before `from` and executing again. visit sees the machine before every replayed instruction.
+    //The history after `to` is dropped.
+    pub fn replay<F: FnMut(&Chip8, u64)>(&mut self, from: u64, to: u64, mut visit: F) -> Result<(), String> {
+        let mut history = self.history.take().ok_or("No history is recorded")?;
+        let start = match history.nearest(from.min(to)) {
+            Some(snapshot) => {
+                self.restore(snapshot);
+                snapshot.cycles
+            }
+            None => {
+                self.history = Some(history);
+                return Err("No history is recorded".to_string());
+            }
+        };
+
+        self.replaying = true;
+        {
+            let mut snapshots = history.snapshots_after(start).peekable();
+            let mut events = history.events_from(start).peekable();
+            loop {
+                //Snapshots are authoritative, they include what the debugger changed
+                if let Some(snapshot) = snapshots.next_if(|s| s.cycles == self.cycles) {
+                    self.restore(snapshot);
+                }
+                while let Some((_, event)) = events.next_if(|(at, _)| *at <= self.cycles) {
+                    match *event {
+                        Event::Key(key, pressed) => self.chip.set_key(key, pressed),
+                    }
+                }
+                if self.cycles >= to || self.fault.is_some() {
+                    break;
+                }
+                visit(&self.chip, self.cycles);
+                self.step();
+            }
+        }
+        self.replaying = false;
+        self.beeping = self.chip.sound_active();
+
+        history.truncate(self.cycles);
+        self.history = Some(history);
+        Ok(())
+    }
+
+    fn restore(&mut self, snapshot: &Snapshot) {
+        self.chip = snapshot.chip.clone();
+        self.chip.dirty = true;
+        self.cycles = snapshot.cycles;
+        self.frame_cycles = snapshot.frame_cycles;
+        self.carry = snapshot.carry;
+        //The polls are from the future of the snapshot, replaying the instructions makes them again
+        self.polled = [None; 16];
+        self.fault = None;
+        self.edited = false;
+    }
+
+    pub fn fault(&self) -> Option<Fault> {
+        self.fault
+    }
+
+    //The picture to show, one byte per pixel row by row from 0 for the background to 255 for lit.
+    //Without an anti-flicker mode it is the display of the machine.
+    pub fn framebuffer(&self) -> &[u8; 32*64] {
+        self.display.pixels(&self.chip.gfx)
+    }
+
+    //Writes the framebuffer in the colors of the palette to a PNG, every pixel scaled up to a square of
+    //the screenshot scale
+    pub fn screenshot(&self, path: &str) -> Result<(), String> {
+        let [width, height] = self.resolution();
+        let scale = self.settings.screenshot_scale.unwrap_or(1).max(1) as usize;
+        let rgb = image::scale_rgb(self.framebuffer(), [width, height], scale, &self.palette());
+        image::write_png(path, (width * scale) as u32, (height * scale) as u32, &rgb)
+    }
+
+    //"Brix-20240131-154502.png" for a screenshot or recording of the running rom started now
+    pub fn capture_name(&self, extension: &str) -> String {
+        let name = if self.rom_name.is_empty() { "rust_8" } else { &self.rom_name };
+        format!("{}-{}.{}", name, local_timestamp(), extension)
+    }
+
+    //Records the presented frames to a GIF or APNG and the beeper to a WAV of the same name, at the screenshot scale
+    pub fn start_recording(&mut self, path: &str) -> Result<(), String> {
+        let scale = self.settings.screenshot_scale.unwrap_or(1);
+        self.recorder = Some(record::new_recorder(path, self.resolution(), scale, self.palette())?);
+        Ok(())
+    }
+
+    //Finishes the recording and tells where it went
+    pub fn stop_recording(&mut self) -> Option<String> {
+        let recorder = self.recorder.take()?;
+        let message = format!("Recorded {} frames to {} and {}", recorder.frames(), recorder.path(), record::wav_path(recorder.path()));
+        drop(recorder);
+        Some(message)
+    }
+
+    pub fn recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    pub fn resolution(&self) -> [usize; 2] {
+        self.chip.resolution()
+    }
+
+    //Whether the framebuffer changed since the last call
+    pub fn take_screen_dirty(&mut self) -> bool {
+        let chip = std::mem::replace(&mut self.chip.dirty, false);
+        let display = self.display.take_dirty();
+        match self.display.mode() {
+            Mode::Off => chip || display,
+            _ => display,
+        }
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.palette.unwrap_or_else(palette::default_palette)
+    }
+
+    //Switches to the next named palette and returns its name
+    pub fn cycle_palette(&mut self) -> &'static str {
+        let name = palette::next(self.settings.palette.as_deref());
+        self.settings.palette = Some(vec![name.to_string()]);
+        self.palette = palette::parse(&[name.to_string()]).ok();
+        name
+    }
+
+    //Stores the speed, quirks, palette and keymap of the running rom in its section of the config file
+    pub fn save_rom_settings(&mut self) -> Result<String, String> {
+        let hash = self.rom_hash.clone().ok_or("No rom is running")?;
+        let options = Options {
+            ips: self.settings.ips.or(Some(self.ips)),
+            quirks: self.settings.quirks.clone().or_else(|| self.chip.quirks().name().map(|n| n.to_string())),
+            palette: self.settings.palette.clone().or_else(|| self.palette.map(|p| p.iter().map(|c| palette::to_hex(*c)).collect())),
+            keymap: self.settings.keymap.clone(),
+            keys: self.settings.keys.clone(),
+            flicker: self.settings.flicker.clone(),
+            ..Options::default()
+        };
+        let path = self.config.save_rom_options(&hash, &options)?;
+        Ok(format!("Saved settings for {} to {}", hash, path.display()))
+    }
+
+    //The keyboard key that presses a hex key, "1" or "Q", for the on-screen keypad
+    pub fn key_label(&self, hex: u8) -> String {
+        self.keymap.keys_of(hex).first().map(|key| keymap::label(*key)).unwrap_or_default()
+    }
+
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    //Uses the keys instead of the preset; saving the rom's settings stores them
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.settings.keys = Some(keymap.to_names());
+        self.keymap = keymap;
+    }
+
+    //Presses or releases the hex key of a keyboard key, other keys do nothing
+    pub fn handle_input(&mut self, key: Key, is_pressed: bool) {
+        if let Some(hex) = self.keymap.get(key) {
+            self.set_key(hex, is_pressed);
         }
     }
 }
 
 pub fn new_platform() -> Platform {
-    return Platform {
+    Platform {
         chip: new_chip8(),
-        clock: 60,
-    };
+        db: romdb::load_db(),
+        ips: DEFAULT_TICK_RATE * 60,
+        palette: None,
+        keymap: keymap::preset(keymap::DEFAULT).unwrap(),
+        rom_keys: Keymap::default(),
+        options: Options::default(),
+        config: Config::default(),
+        settings: Options::default(),
+        rom_hash: None,
+        rom_name: String::new(),
+        beeping: false,
+        frame_cycles: 0,
+        carry: 0,
+        fault: None,
+        cycles: 0,
+        history: None,
+        edited: false,
+        replaying: false,
+        symbols: Symbols::default(),
+        tracer: None,
+        profiler: None,
+        heatmap: None,
+        coverage: None,
+        display: flicker::new_display(Mode::Off),
+        recorder: None,
+        polled: [None; 16],
+    }
+}
+
+fn unix_time() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+//The local time as YYYYMMDD-HHMMSS
+#[cfg(unix)]
+fn local_timestamp() -> String {
+    let now = unix_time() as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe {
+        libc::localtime_r(&now, &mut tm);
+    }
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
+
+//The time in UTC where the time zone isn't at hand, as YYYYMMDD-HHMMSS
+#[cfg(not(unix))]
+fn local_timestamp() -> String {
+    let now = unix_time();
+    let (days, secs) = ((now / 86400) as i64, now % 86400);
+    //The civil date of a day since 1970, years start in March so that the leap day comes last
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
 
-}
\ No newline at end of file
+    #[test]
+    fn ips_between_multiples_of_60() {
+        for ips in [90, 100, 700] {
+            let mut platform = new_platform();
+            platform.set_options(Options { ips: Some(ips), ..Options::default() });
+            platform.open_rom_data("loop.ch8", vec![0x12, 0x00]);
+            for _ in 0..60 {
+                platform.c8_cycle();
+            }
+            assert_eq!(platform.cycles(), ips as u64);
+        }
+    }
+
+    #[test]
+    fn polled_keys_after_stepping_back() {
+        let mut platform = new_platform();
+        platform.record_history();
+        //LD V0, K waits for a key
+        platform.open_rom_data("key.ch8", vec![0xf0, 0x0a]);
+        for _ in 0..3 {
+            platform.c8_cycle();
+        }
+        platform.replay(0, 1, |_, _| {}).unwrap();
+        assert_eq!(platform.polled_keys(), [true; 16]);
+        platform.replay(0, 0, |_, _| {}).unwrap();
+        assert_eq!(platform.polled_keys(), [false; 16]);
+    }
+
+    #[test]
+    fn saved_settings_round_trip() {
+        let dir = std::env::temp_dir().join(format!("rust_8-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        fs::write(&path, "[rom.\"test.ch8\"]\nips = 700\nquirks = \"vip\"\n").unwrap();
+
+        let mut platform = new_platform();
+        platform.set_config(config::load(Some(&path)).unwrap());
+        platform.open_rom_data("test.ch8", vec![0x12, 0x00]);
+        platform.save_rom_settings().unwrap();
+        let saved = config::load(Some(&path)).unwrap();
+        let options = saved.rom_options(platform.rom_hash.as_deref().unwrap(), "");
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(options.ips, Some(700));
+        assert_eq!(options.quirks.as_deref(), Some("vip"));
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use crate::chip8::Chip8;
+use crate::disasm;
+use crate::symbols::Symbols;
+
+//Rows of the hot spot report
+const REPORT_ROWS: usize = 20;
+
+//A subroutine being executed: where it starts, the call instruction that entered it and the
+//instruction count when it did
+struct Frame {
+    entry: u16,
+    site: u16,
+    start: u64,
+}
+
+//Counts the instructions executed at every address and attributes them to subroutines with a call stack
+//kept along the 2NNN and 00EE instructions. Every instruction costs one cycle in this interpreter, so
+//instruction counts are the cycles.
+pub struct Profiler {
+    rom: String,
+    total: u64,
+    stack: Vec<Frame>,
+    //the rom's entry point at the bottom
+    costs: HashMap<(u16, u16), u64>,
+    //instructions executed per subroutine and address
+    stacks: HashMap<Vec<u16>, u64>,
+    //instructions executed per call stack of subroutine entries, for the collapsed stacks
+    calls: HashMap<(u16, u16, u16), (u64, u64)>,
+    //calls and their inclusive cost per caller, call site and callee
+}
+
+impl Profiler {
+    //Counts the instruction about to execute
+    pub fn before(&mut self, chip: &Chip8) {
+        if self.stack.is_empty() {
+            self.stack.push(Frame { entry: chip.pc, site: chip.pc, start: 0 });
+        }
+        let function = self.stack.last().map_or(chip.pc, |frame| frame.entry);
+        *self.costs.entry((function, chip.pc)).or_insert(0) += 1;
+        let entries: Vec<u16> = self.stack.iter().map(|frame| frame.entry).collect();
+        match self.stacks.get_mut(&entries) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(entries, 1);
+            }
+        }
+        self.total += 1;
+    }
+
+    //Follows the call stack of the machine after an instruction executed
+    pub fn after(&mut self, chip: &Chip8) {
+        let depth = chip.sp as usize + 1;
+        while self.stack.len() > depth.max(1) {
+            let frame = self.stack.pop().unwrap();
+            let caller = self.stack.last().map_or(frame.entry, |caller| caller.entry);
+            let call = self.calls.entry((caller, frame.site, frame.entry)).or_insert((0, 0));
+            call.0 += 1;
+            call.1 += self.total - frame.start;
+        }
+        if self.stack.len() < depth {
+            let site = chip.stack[chip.sp as usize - 1].wrapping_sub(2);
+            self.stack.push(Frame { entry: chip.pc, site, start: self.total });
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    //Executions per address, the subroutines of an address add up
+    fn addresses(&self) -> HashMap<u16, u64> {
+        let mut counts = HashMap::new();
+        for ((_, pc), count) in &self.costs {
+            *counts.entry(*pc).or_insert(0) += count;
+        }
+        counts
+    }
+
+    //Self and inclusive instructions per subroutine entry
+    fn functions(&self) -> HashMap<u16, (u64, u64)> {
+        let mut functions: HashMap<u16, (u64, u64)> = HashMap::new();
+        for ((function, _), count) in &self.costs {
+            functions.entry(*function).or_default().0 += count;
+        }
+        for (entries, count) in &self.stacks {
+            let mut seen = Vec::new();
+            //A recursive subroutine counts once per stack
+            for entry in entries {
+                if !seen.contains(entry) {
+                    seen.push(*entry);
+                    functions.entry(*entry).or_default().1 += count;
+                }
+            }
+        }
+        functions
+    }
+
+    //The busiest addresses and subroutines
+    pub fn report(&self, mem: &[u8], symbols: &Symbols) -> String {
+        let mut out = String::new();
+        let total = self.total.max(1) as f64;
+        let _ = writeln!(out, "{} instructions executed", self.total);
+
+        let mut addresses: Vec<(u16, u64)> = self.addresses().into_iter().collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let _ = writeln!(out, "\nHot spots:");
+        for (addr, count) in addresses.iter().take(REPORT_ROWS) {
+            let opcode = disasm::fetch(mem, *addr as usize);
+            let _ = writeln!(out, "{:>10} {:>6.2}%  {:#05x}  {:<20} {}", count, *count as f64 * 100.0 / total, addr,
+                disasm::disassemble_symbols(opcode, symbols), symbols.describe(*addr).unwrap_or_default());
+        }
+
+        let mut functions: Vec<(u16, (u64, u64))> = self.functions().into_iter().collect();
+        functions.sort_by(|a, b| (b.1).1.cmp(&(a.1).1).then(a.0.cmp(&b.0)));
+        let _ = writeln!(out, "\nSubroutines:       self       inclusive");
+        for (entry, (own, inclusive)) in functions.iter().take(REPORT_ROWS) {
+            let _ = writeln!(out, "{:<16} {:>10} {:>6.2}%  {:>10} {:>6.2}%", function_name(*entry, symbols),
+                own, *own as f64 * 100.0 / total, inclusive, *inclusive as f64 * 100.0 / total);
+        }
+        out
+    }
+
+    //One line per call stack, "main;draw_score 1234", the input of flamegraph.pl and inferno
+    pub fn collapsed(&self, symbols: &Symbols) -> String {
+        let mut lines: Vec<String> = self.stacks.iter().map(|(entries, count)| {
+            let names: Vec<String> = entries.iter().map(|entry| function_name(*entry, symbols)).collect();
+            format!("{} {}", names.join(";"), count)
+        }).collect();
+        lines.sort();
+        lines.join("\n") + "\n"
+    }
+
+    //The callgrind format of KCachegrind, with addresses as instruction positions and the source lines when known
+    pub fn callgrind(&self, symbols: &Symbols) -> String {
+        let rom = &self.rom;
+        let mut out = String::new();
+        let with_lines = symbols.source().is_some();
+        let _ = writeln!(out, "# callgrind format");
+        let _ = writeln!(out, "version: 1");
+        let _ = writeln!(out, "creator: rust_8");
+        let _ = writeln!(out, "cmd: {}", rom);
+        let _ = writeln!(out, "positions: {}", if with_lines { "instr line" } else { "instr" });
+        let _ = writeln!(out, "events: Instructions");
+        let _ = writeln!(out, "summary: {}", self.total);
+        let file = symbols.source().map_or(rom.clone(), |path| path.display().to_string());
+        let position = |addr: u16| match symbols.line(addr) {
+            Some(line) if with_lines => format!("{:#x} {}", addr, line),
+            _ if with_lines => format!("{:#x} 0", addr),
+            _ => format!("{:#x}", addr),
+        };
+
+        //Calls still running when the profile is written count up to now
+        let mut calls = self.calls.clone();
+        for (n, frame) in self.stack.iter().enumerate().skip(1) {
+            let call = calls.entry((self.stack[n - 1].entry, frame.site, frame.entry)).or_insert((0, 0));
+            call.0 += 1;
+            call.1 += self.total - frame.start;
+        }
+
+        let mut functions: Vec<u16> = self.costs.keys().map(|(function, _)| *function).collect();
+        functions.sort_unstable();
+        functions.dedup();
+        for function in functions {
+            let _ = writeln!(out, "\nfl={}", file);
+            let _ = writeln!(out, "fn={}", function_name(function, symbols));
+            let mut costs: Vec<(u16, u64)> = self.costs.iter()
+                .filter(|((f, _), _)| *f == function)
+                .map(|((_, pc), count)| (*pc, *count))
+                .collect();
+            costs.sort_unstable();
+            for (pc, count) in costs {
+                let _ = writeln!(out, "{} {}", position(pc), count);
+            }
+            let mut callees: Vec<_> = calls.iter().filter(|((caller, _, _), _)| *caller == function).collect();
+            callees.sort_unstable();
+            for ((_, site, callee), (count, inclusive)) in callees {
+                let _ = writeln!(out, "cfn={}", function_name(*callee, symbols));
+                let _ = writeln!(out, "calls={} {}", count, position(*callee));
+                let _ = writeln!(out, "{} {}", position(*site), inclusive);
+            }
+        }
+        out
+    }
+}
+
+fn function_name(entry: u16, symbols: &Symbols) -> String {
+    match symbols.label(entry) {
+        Some(label) => label.to_string(),
+        None => format!("sub_{:03x}", entry),
+    }
+}
+
+pub fn new_profiler(rom: &str) -> Profiler {
+    Profiler {
+        rom: rom.to_string(),
+        total: 0,
+        stack: Vec::new(),
+        costs: HashMap::new(),
+        stacks: HashMap::new(),
+        calls: HashMap::new(),
+    }
+}
@@ -0,0 +1,251 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::image;
+use crate::palette::{self, Palette};
+
+const FPS: u64 = 60;
+//Browsers slow down GIF frames shorter than this, in hundredths of a second
+const MIN_DELAY: u64 = 2;
+const SAMPLE_RATE: u32 = 44100;
+const BEEP_HZ: u32 = 440;
+const VOLUME: i16 = 6000;
+
+//The colors of the 256 framebuffer values of a palette, the framebuffer is then the GIF's pixel indices
+fn gif_palette(palette: &Palette) -> Vec<u8> {
+    (0..=255).flat_map(|value| image::rgb(palette::shade(palette, value))).collect()
+}
+
+//Time from the start of the recording to a frame in the hundredths of a second of GIF delays
+fn centiseconds(frame: u64) -> u64 {
+    frame * 100 / FPS
+}
+
+//A frame that has not been written yet, it lasts as long as the following frames are the same
+struct Pending {
+    pixels: Vec<u8>,
+    palette: Palette,
+    start: u64,
+}
+
+//Mono 16 bit PCM of the beeper, a square wave while the sound timer runs
+struct Wav {
+    out: BufWriter<File>,
+    samples: u32,
+    phase: u32,
+}
+
+impl Wav {
+    fn frame(&mut self, beeping: bool) -> std::io::Result<()> {
+        let count = SAMPLE_RATE / FPS as u32;
+        for _ in 0..count {
+            let sample = if !beeping {
+                0
+            } else if self.phase < SAMPLE_RATE / 2 {
+                VOLUME
+            } else {
+                -VOLUME
+            };
+            self.phase = (self.phase + BEEP_HZ) % SAMPLE_RATE;
+            self.out.write_all(&sample.to_le_bytes())?;
+        }
+        self.samples += count;
+        Ok(())
+    }
+
+    //Fills in the sizes of the header once the samples are known
+    fn finish(&mut self) -> std::io::Result<()> {
+        let data_size = self.samples * 2;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(36 + data_size).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&data_size.to_le_bytes())?;
+        self.out.flush()
+    }
+}
+
+fn new_wav(path: &str) -> Result<Wav, String> {
+    let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut out = BufWriter::new(file);
+    let mut header = Vec::new();
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&36u32.to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    //PCM, one channel
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    header.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&0u32.to_le_bytes());
+    out.write_all(&header).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(Wav { out, samples: 0, phase: 0 })
+}
+
+//The encoder of the recording's format
+enum Video {
+    Gif(gif::Encoder<BufWriter<File>>),
+    //APNG has the number of frames before the first one, so they are kept with their length in frames
+    //until the end
+    Apng(BufWriter<File>, Vec<(Pending, u64)>),
+}
+
+//Records every presented frame into an animated GIF or APNG and the beeper into a WAV next to it.
+//Frames that are the same as the one before only lengthen its delay. The files are complete when it is
+//dropped.
+pub struct Recorder {
+    path: String,
+    video: Video,
+    wav: Wav,
+    resolution: [usize; 2],
+    scale: usize,
+    frames: u64,
+    pending: Option<Pending>,
+    first_palette: Palette,
+    //the GIF's global palette
+}
+
+impl Recorder {
+    pub fn frame(&mut self, framebuffer: &[u8], palette: Palette, beeping: bool) -> Result<(), String> {
+        self.wav.frame(beeping).map_err(|e| format!("{}: {}", self.path, e))?;
+        let same = match &self.pending {
+            Some(pending) => pending.pixels.as_slice() == framebuffer && pending.palette == palette,
+            None => false,
+        };
+        if !same {
+            match &mut self.pending {
+                //A frame too short to show in a GIF is replaced by the next one
+                Some(pending) if matches!(self.video, Video::Gif(_))
+                    && centiseconds(self.frames) - centiseconds(pending.start) < MIN_DELAY => {
+                    pending.pixels = framebuffer.to_vec();
+                    pending.palette = palette;
+                }
+                _ => {
+                    self.write_pending()?;
+                    self.pending = Some(Pending { pixels: framebuffer.to_vec(), palette, start: self.frames });
+                }
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    //Writes the pending frame with the time it was shown
+    fn write_pending(&mut self) -> Result<(), String> {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        let gif = match &mut self.video {
+            Video::Gif(gif) => gif,
+            Video::Apng(_, frames) => {
+                let length = self.frames - pending.start;
+                frames.push((pending, length));
+                return Ok(());
+            }
+        };
+        let delay = (centiseconds(self.frames) - centiseconds(pending.start)).min(u16::MAX as u64) as u16;
+
+        let [width, height] = self.resolution;
+        let scale = self.scale;
+        let mut buffer = Vec::with_capacity(width * height * scale * scale);
+        for y in 0..height * scale {
+            for x in 0..width * scale {
+                buffer.push(pending.pixels.get((y / scale) * width + x / scale).copied().unwrap_or(0));
+            }
+        }
+        let frame = gif::Frame {
+            width: (width * scale) as u16,
+            height: (height * scale) as u16,
+            delay,
+            buffer: Cow::Owned(buffer),
+            palette: if pending.palette == self.first_palette { None } else { Some(gif_palette(&pending.palette)) },
+            ..gif::Frame::default()
+        };
+        gif.write_frame(&frame).map_err(|e| format!("{}: {}", self.path, e))
+    }
+
+    //Writes the kept frames of an APNG in RGB, the palette might change between them
+    fn write_apng(&mut self) -> Result<(), String> {
+        let (out, frames) = match &mut self.video {
+            Video::Apng(out, frames) if !frames.is_empty() => (out, frames),
+            _ => return Ok(()),
+        };
+        let path = &self.path;
+        let error = |e: png::EncodingError| format!("{}: {}", path, e);
+        let [width, height] = self.resolution;
+        let mut encoder = png::Encoder::new(out, (width * self.scale) as u32, (height * self.scale) as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(frames.len() as u32, 0).map_err(error)?;
+        let mut writer = encoder.write_header().map_err(error)?;
+        for (pending, length) in frames.iter() {
+            writer.set_frame_delay((*length).min(u16::MAX as u64) as u16, FPS as u16).map_err(error)?;
+            writer.write_image_data(&image::scale_rgb(&pending.pixels, self.resolution, self.scale, &pending.palette)).map_err(error)?;
+        }
+        writer.finish().map_err(error)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.write_pending().and_then(|()| self.write_apng()) {
+            println!("{}", e);
+        }
+        if let Err(e) = self.wav.finish() {
+            println!("Error writing the sound of {}: {}", self.path, e);
+        }
+    }
+}
+
+//The WAV goes next to the video with the same name
+pub fn wav_path(path: &str) -> String {
+    Path::new(path).with_extension("wav").to_string_lossy().to_string()
+}
+
+pub fn new_recorder(path: &str, resolution: [usize; 2], scale: u32, palette: Palette) -> Result<Recorder, String> {
+    let extension = Path::new(path).extension().map(|e| e.to_string_lossy().to_lowercase());
+    let gif = match extension.as_deref() {
+        Some("gif") => true,
+        Some("png") | Some("apng") => false,
+        _ => return Err(format!("{}: recordings are written as .gif, .png or .apng", path)),
+    };
+    let scale = scale.max(1) as usize;
+    let size = [resolution[0] * scale, resolution[1] * scale];
+    if size.iter().any(|side| *side > u16::MAX as usize) {
+        return Err(format!("{}: {}x{} is too large to record, lower the screenshot scale", path, size[0], size[1]));
+    }
+    let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+    let video = if gif {
+        let mut gif = gif::Encoder::new(BufWriter::new(file), size[0] as u16, size[1] as u16, &gif_palette(&palette))
+            .map_err(|e| format!("{}: {}", path, e))?;
+        gif.set_repeat(gif::Repeat::Infinite).map_err(|e| format!("{}: {}", path, e))?;
+        Video::Gif(gif)
+    } else {
+        Video::Apng(BufWriter::new(file), Vec::new())
+    };
+    let wav = new_wav(&wav_path(path))?;
+    Ok(Recorder {
+        path: path.to_string(),
+        video,
+        wav,
+        resolution,
+        scale,
+        frames: 0,
+        pending: None,
+        first_palette: palette,
+    })
+}
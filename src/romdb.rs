@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::chip8;
+use crate::config;
+use crate::palette;
+
+const EMBEDDED_DB: &str = include_str!("../data/romdb.toml");
+
+//Recommended settings for a rom, every field is optional
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RomEntry {
+    pub title: Option<String>,
+    pub platform: Option<String>,
+    pub quirks: Option<String>,
+    pub tick_rate: Option<u32>,
+    pub keymap: Option<HashMap<String, u8>>,
+    #[serde(default, deserialize_with = "palette::deserialize")]
+    pub palette: Option<Vec<String>>,
+    pub load_address: Option<u16>,
+}
+
+impl RomEntry {
+    //Fills the fields missing in self from a lower priority entry
+    fn merge(&mut self, base: &RomEntry) {
+        let base = base.clone();
+        self.title = self.title.take().or(base.title);
+        self.platform = self.platform.take().or(base.platform);
+        self.quirks = self.quirks.take().or(base.quirks);
+        self.tick_rate = self.tick_rate.or(base.tick_rate);
+        self.keymap = self.keymap.take().or(base.keymap);
+        self.palette = self.palette.take().or(base.palette);
+        self.load_address = self.load_address.or(base.load_address);
+    }
+}
+
+pub struct RomDb {
+    entries: HashMap<String, RomEntry>,
+    user_entries: HashMap<String, RomEntry>,
+}
+
+impl RomDb {
+    //The user database overrides single fields of the embedded one
+    pub fn lookup(&self, hash: &str) -> Option<RomEntry> {
+        let hash = hash.to_lowercase();
+        match (self.user_entries.get(&hash), self.entries.get(&hash)) {
+            (Some(user), Some(base)) => {
+                let mut entry = user.clone();
+                entry.merge(base);
+                Some(entry)
+            }
+            (Some(entry), None) | (None, Some(entry)) => Some(entry.clone()),
+            (None, None) => None,
+        }
+    }
+}
+
+pub fn sha1_hex(data: &[u8]) -> String {
+    sha1_smol::Sha1::from(data).digest().to_string()
+}
+
+pub fn user_db_path() -> Option<PathBuf> {
+    config::config_dir().map(|dir| dir.join("romdb.toml"))
+}
+
+fn parse_db(text: &str) -> Result<HashMap<String, RomEntry>, String> {
+    let entries: HashMap<String, RomEntry> = toml::from_str(text).map_err(|e| e.to_string())?;
+    for (hash, entry) in &entries {
+        if let Some(addr) = entry.load_address.filter(|addr| *addr as usize >= chip8::MEM_SIZE) {
+            return Err(format!("the load address {:#x} of {} is outside of memory", addr, hash));
+        }
+    }
+    Ok(entries.into_iter().map(|(hash, entry)| (hash.to_lowercase(), entry)).collect())
+}
+
+pub fn load_db() -> RomDb {
+    let entries = parse_db(EMBEDDED_DB).expect("Error parsing the embedded rom database");
+
+    let mut user_entries = HashMap::new();
+    if let Some(path) = user_db_path() {
+        if let Ok(text) = fs::read_to_string(&path) {
+            match parse_db(&text) {
+                Ok(parsed) => user_entries = parsed,
+                Err(e) => println!("Ignoring {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    RomDb { entries, user_entries }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_address_outside_of_memory() {
+        assert!(parse_db("[abc]\nload_address = 0x600\n").is_ok());
+        assert!(parse_db("[abc]\nload_address = 0x1000\n").is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use graphics::{Context, Image};
+use opengl_graphics::{CreateTexture, Filter, Format, GlGraphics, Texture, TextureSettings, UpdateTexture};
+
+use crate::image;
+use crate::palette::{self, Palette};
+
+//The chip8 display as one texture with a texel per pixel. It is only uploaded again when the framebuffer
+//or the palette changed, and made anew when the resolution changes.
+pub struct Screen {
+    texture: Texture,
+    resolution: [usize; 2],
+    pixels: Vec<u8>,
+    //RGBA of the texture
+    palette: Palette,
+    smooth: bool,
+    //linear filtering and any scale instead of whole multiples of the resolution
+}
+
+impl Screen {
+    pub fn update(&mut self, framebuffer: &[u8], resolution: [usize; 2], palette: Palette) {
+        self.palette = palette;
+        if resolution != self.resolution {
+            *self = new_screen(resolution, self.smooth);
+            self.palette = palette;
+        }
+        for (pixel, rgba) in framebuffer.iter().zip(self.pixels.chunks_mut(4)) {
+            let [r, g, b] = image::rgb(palette::shade(&palette, *pixel));
+            rgba.copy_from_slice(&[r, g, b, 255]);
+        }
+        let size = [resolution[0] as u32, resolution[1] as u32];
+        if let Err(e) = UpdateTexture::update(&mut self.texture, &mut (), Format::Rgba8, &self.pixels, [0, 0], size) {
+            println!("Error updating the screen texture: {}", e);
+        }
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    //Where the display goes in the window: as large as it fits with its aspect ratio, centered between
+    //black bars. Whole multiples of the resolution count in physical pixels, draw_size being the window
+    //size in them on HiDPI screens.
+    pub fn fit(&self, window_size: [f64; 2], draw_size: [u32; 2]) -> [f64; 4] {
+        let [width, height] = [self.resolution[0] as f64, self.resolution[1] as f64];
+        let dpi = if window_size[0] > 0.0 { draw_size[0] as f64 / window_size[0] } else { 1.0 };
+        let mut scale = (window_size[0] / width).min(window_size[1] / height);
+        if !self.smooth {
+            scale = (scale * dpi).floor().max(1.0) / dpi;
+        }
+        let size = [width * scale, height * scale];
+        [(window_size[0] - size[0]) / 2.0, (window_size[1] - size[1]) / 2.0, size[0], size[1]]
+    }
+
+    pub fn draw(&self, rect: [f64; 4], c: &Context, gl: &mut GlGraphics) {
+        Image::new().rect(rect).draw(&self.texture, &c.draw_state, c.transform, gl);
+    }
+}
+
+//Needs the OpenGL context of the window
+pub fn new_screen(resolution: [usize; 2], smooth: bool) -> Screen {
+    let filter = if smooth { Filter::Linear } else { Filter::Nearest };
+    let settings = TextureSettings::new().filter(filter).convert_gamma(true);
+    let pixels = vec![0; resolution[0] * resolution[1] * 4];
+    let size = [resolution[0] as u32, resolution[1] as u32];
+    let texture = Texture::create(&mut (), Format::Rgba8, &pixels, size, &settings)
+        .expect("Error creating the screen texture");
+    Screen { texture, resolution, pixels, palette: [[0.0; 4]; 4], smooth }
+}
@@ -0,0 +1,46 @@
+use std::fs;
+
+use crate::platform::Platform;
+
+//Key presses for a run without a window, one per line as "FRAME press|release KEY" with the hex key,
+//e.g. "120 press 5". Lines starting with # are comments.
+pub struct Script {
+    events: Vec<(u32, u8, bool)>,
+    //frame, key and whether it is pressed, by frame
+}
+
+impl Script {
+    //Presses and releases the keys of a frame before it runs
+    pub fn apply(&self, frame: u32, platform: &mut Platform) {
+        for (_, key, is_pressed) in self.events.iter().filter(|event| event.0 == frame) {
+            platform.set_key(*key, *is_pressed);
+        }
+    }
+}
+
+pub fn load(path: &str) -> Result<Script, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut events = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = || format!("{}:{}: expected FRAME press|release KEY, got {}", path, n + 1, line);
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (frame, action, key) = match fields.as_slice() {
+            [frame, action, key] => (frame, action, key),
+            _ => return Err(error()),
+        };
+        let frame = frame.parse::<u32>().map_err(|_| error())?;
+        let is_pressed = match action.to_lowercase().as_str() {
+            "press" => true,
+            "release" => false,
+            _ => return Err(error()),
+        };
+        let key = u8::from_str_radix(key, 16).ok().filter(|key| *key < 16).ok_or_else(error)?;
+        events.push((frame, key, is_pressed));
+    }
+    events.sort_by_key(|event| event.0);
+    Ok(Script { events })
+}
@@ -0,0 +1,170 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use crate::asm::{self, Program};
+
+//Labels and source lines of a rom, loaded from
+// - an assembly source, assembled to get its labels and line table
+// - the JSON symbols exported by Octo, labels are the "labels" object of name to address
+// - a text file with one "ADDR NAME" per line, ADDR in hex. A NAME like "game.asm:12" is a source line,
+//   that's what "rust_8 asm --map" writes.
+#[derive(Default)]
+pub struct Symbols {
+    labels: BTreeMap<u16, String>,
+    addrs: HashMap<String, u16>,
+    source: Option<PathBuf>,
+    lines: BTreeMap<u16, usize>,
+}
+
+impl Symbols {
+    fn add_label(&mut self, addr: u16, name: &str) {
+        //The first label of an address names it, all of them resolve
+        self.labels.entry(addr).or_insert_with(|| name.to_string());
+        self.addrs.insert(name.to_string(), addr);
+    }
+
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(|name| name.as_str())
+    }
+
+    pub fn addr(&self, name: &str) -> Option<u16> {
+        self.addrs.get(name).copied()
+    }
+
+    pub fn source(&self) -> Option<&Path> {
+        self.source.as_deref()
+    }
+
+    pub fn line(&self, addr: u16) -> Option<usize> {
+        self.lines.get(&addr).copied()
+    }
+
+    //Address and source line of every statement
+    pub fn lines(&self) -> impl Iterator<Item = (u16, usize)> + '_ {
+        self.lines.iter().map(|(addr, line)| (*addr, *line))
+    }
+
+    //First statement at or after the line, a breakpoint on a label or comment moves to the next instruction
+    pub fn line_addr(&self, line: usize) -> Option<(usize, u16)> {
+        self.lines.iter().map(|(addr, l)| (*l, *addr)).filter(|(l, _)| *l >= line).min()
+    }
+
+    //Nearest label at or before addr, e.g. "main_loop+4"
+    pub fn offset_label(&self, addr: u16) -> Option<String> {
+        let (start, name) = self.labels.range(..=addr).next_back()?;
+        match addr - start {
+            0 => Some(name.clone()),
+            offset => Some(format!("{}+{}", name, offset)),
+        }
+    }
+
+    //"main_loop+4, game.asm:12" or whatever part is known
+    pub fn describe(&self, addr: u16) -> Option<String> {
+        let line = self.line(addr).zip(self.source.as_ref())
+            .map(|(line, path)| format!("{}:{}", path.file_name().unwrap_or_default().to_string_lossy(), line));
+        match (self.offset_label(addr), line) {
+            (Some(label), Some(line)) => Some(format!("{}, {}", label, line)),
+            (label, line) => label.or(line),
+        }
+    }
+
+    //Address in hex or decimal, or a label
+    pub fn resolve(&self, text: &str) -> Option<u16> {
+        asm::parse_number(text).or_else(|| self.addr(text))
+    }
+
+    //The text of a symbol file, see the top of this file
+    pub fn to_map(&self) -> String {
+        let mut out = String::new();
+        for (addr, name) in &self.labels {
+            out.push_str(&format!("{:#05x} {}\n", addr, name));
+        }
+        for (name, addr) in &self.addrs {
+            if self.labels.get(addr) != Some(name) {
+                out.push_str(&format!("{:#05x} {}\n", addr, name));
+            }
+        }
+        if let Some(source) = &self.source {
+            for (addr, line) in &self.lines {
+                out.push_str(&format!("{:#05x} {}:{}\n", addr, source.display(), line));
+            }
+        }
+        out
+    }
+}
+
+pub fn from_program(program: &Program, source: &Path) -> Symbols {
+    let mut symbols = Symbols::default();
+    let mut labels: Vec<(&String, &u16)> = program.labels.iter().collect();
+    labels.sort();
+    for (name, addr) in labels {
+        symbols.add_label(*addr, name);
+    }
+    symbols.source = Some(source.to_path_buf());
+    for (line, addr) in &program.lines {
+        symbols.lines.entry(*addr).or_insert(*line);
+    }
+    symbols
+}
+
+fn parse_hex(text: &str) -> Option<u16> {
+    let lower = text.to_lowercase();
+    let digits = lower.strip_prefix("0x").or(lower.strip_prefix('$')).or(lower.strip_prefix('#')).unwrap_or(&lower);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+fn parse_map(text: &str, dir: &Path) -> Result<Symbols, String> {
+    let mut symbols = Symbols::default();
+    for (n, raw) in text.lines().enumerate() {
+        let line = raw.split(';').next().unwrap_or("").trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let (addr, name) = match (words.next().and_then(parse_hex), words.next()) {
+            (Some(addr), Some(name)) => (addr, name),
+            _ => return Err(format!("line {}: expected ADDR NAME", n + 1)),
+        };
+        //A source line is "FILE:LINE", relative to the symbol file
+        match name.rsplit_once(':').and_then(|(file, line)| line.parse::<usize>().ok().map(|line| (file, line))) {
+            Some((file, source_line)) => {
+                symbols.source = Some(dir.join(file));
+                symbols.lines.insert(addr, source_line);
+            }
+            None => symbols.add_label(addr, name),
+        }
+    }
+    Ok(symbols)
+}
+
+fn parse_octo(text: &str) -> Result<Symbols, String> {
+    let json: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let labels = json.get("labels").unwrap_or(&json).as_object().ok_or("expected an object of labels")?;
+    let mut symbols = Symbols::default();
+    for (name, addr) in labels {
+        if let Some(addr) = addr.as_u64().filter(|a| *a < 0x1000) {
+            symbols.add_label(addr as u16, name);
+        }
+    }
+    Ok(symbols)
+}
+
+pub fn load(path: &Path) -> Result<Symbols, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let symbols = match extension.as_str() {
+        "asm" | "s" => asm::assemble(&text).map(|program| from_program(&program, path)).map_err(|e| e.to_string()),
+        "json" => parse_octo(&text),
+        _ => parse_map(&text, path.parent().unwrap_or(Path::new(""))),
+    };
+    symbols.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+//The symbol file next to a rom, game.ch8 comes with game.sym
+pub fn find(rom: &Path) -> Option<PathBuf> {
+    let path = rom.with_extension("sym");
+    if path.exists() { Some(path) } else { None }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::{Hide, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::style::ResetColor;
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use piston::Key;
+
+use crate::dap::Dap;
+use crate::image;
+use crate::keymap;
+use crate::palette::{self, Palette};
+use crate::platform::Platform;
+
+//Most terminals only send key presses, repeated while a key is held, so a key is released this many
+//frames after its last keystroke
+const HOLD_FRAMES: u64 = 10;
+const FRAME: Duration = Duration::from_micros(16_667);
+
+//What the keyboard asked for in a frame
+enum Input {
+    Key(Key),
+    //only terminals that report released keys send these, like the Windows console
+    Release(Key),
+    NextPalette,
+    Screenshot,
+    Record,
+    Quit,
+}
+
+//Raw mode for as long as the frontend runs: keystrokes arrive one at a time without echo. The screen is
+//drawn on the alternate screen, the terminal is restored on drop.
+struct RawMode;
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), ResetColor, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn raw_mode() -> Result<RawMode, String> {
+    if !io::stdin().is_terminal() {
+        return Err("The terminal frontend needs a terminal to read the keys from".to_string());
+    }
+    terminal::enable_raw_mode().map_err(|e| format!("Error switching the terminal to raw mode: {}", e))?;
+    let raw = RawMode;
+    execute!(io::stdout(), EnterAlternateScreen, Hide, Clear(ClearType::All)).map_err(|e| e.to_string())?;
+    Ok(raw)
+}
+
+//The keys of the keypad the terminal can send: letters, digits and the arrows
+fn key_of(code: KeyCode) -> Option<Key> {
+    match code {
+        KeyCode::Char(c) if c.is_ascii_digit() => keymap::parse_key(&format!("D{}", c)),
+        KeyCode::Char(c) if c.is_ascii_alphabetic() => keymap::parse_key(&c.to_ascii_uppercase().to_string()),
+        KeyCode::Up => Some(Key::Up),
+        KeyCode::Down => Some(Key::Down),
+        KeyCode::Left => Some(Key::Left),
+        KeyCode::Right => Some(Key::Right),
+        _ => None,
+    }
+}
+
+//The keystrokes that arrived since the last call
+fn read_input() -> Vec<Input> {
+    let mut inputs = Vec::new();
+    while let Ok(true) = event::poll(Duration::ZERO) {
+        let key = match event::read() {
+            Ok(Event::Key(key)) => key,
+            Ok(_) => continue,
+            Err(_) => break,
+        };
+        if key.kind == KeyEventKind::Release {
+            inputs.extend(key_of(key.code).map(Input::Release));
+            continue;
+        }
+        let input = match key.code {
+            KeyCode::Esc => Some(Input::Quit),
+            KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => match c {
+                'c' => Some(Input::Quit),
+                'p' => Some(Input::NextPalette),
+                's' => Some(Input::Screenshot),
+                'r' => Some(Input::Record),
+                _ => None,
+            },
+            code => key_of(code).map(Input::Key),
+        };
+        inputs.extend(input);
+    }
+    inputs
+}
+
+fn set_color(out: &mut String, code: u8, color: [f32; 4]) {
+    let [r, g, b] = image::rgb(color);
+    let _ = write!(out, "\x1b[{};2;{};{};{}m", code, r, g, b);
+}
+
+//Two pixels above each other per character: the upper half block in the color of the top pixel on the
+//background of the bottom one
+fn draw_half_blocks(out: &mut String, framebuffer: &[u8], [width, height]: [usize; 2], palette: &Palette) {
+    for y in (0..height).step_by(2) {
+        let mut last = None;
+        for x in 0..width {
+            let top = framebuffer[y * width + x];
+            let bottom = framebuffer.get((y + 1) * width + x).copied().unwrap_or(0);
+            if last != Some((top, bottom)) {
+                set_color(out, 38, palette::shade(palette, top));
+                set_color(out, 48, palette::shade(palette, bottom));
+                last = Some((top, bottom));
+            }
+            out.push('▀');
+        }
+        out.push_str("\x1b[0m\r\n");
+    }
+}
+
+//2x4 pixels per braille character, lit or not, for terminals too small for the half blocks
+fn draw_braille(out: &mut String, framebuffer: &[u8], [width, height]: [usize; 2], palette: &Palette) {
+    //The dot of each pixel of a cell by row and column
+    const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+    for y in (0..height).step_by(4) {
+        set_color(out, 38, palette[1]);
+        set_color(out, 48, palette[0]);
+        for x in (0..width).step_by(2) {
+            let mut bits = 0;
+            for (row, dots) in DOTS.iter().enumerate() {
+                for (column, dot) in dots.iter().enumerate() {
+                    let lit = (y + row < height && x + column < width) && framebuffer[(y + row) * width + x + column] >= 0x80;
+                    if lit {
+                        bits |= dot;
+                    }
+                }
+            }
+            out.push(std::char::from_u32(0x2800 + bits).unwrap_or(' '));
+        }
+        out.push_str("\x1b[0m\r\n");
+    }
+}
+
+//Runs the rom opened in the platform in the terminal, a frame every 60th of a second or as the debug
+//adapter lets it, until Esc or Ctrl-C
+pub fn run(platform: &mut Platform, title: &str, braille: bool, mut dap: Option<Dap>) -> Result<(), String> {
+    let _raw = raw_mode()?;
+    let mut held: HashMap<Key, u64> = HashMap::new();
+    //keys pressed and the frame they are released at
+    let mut frame = 0;
+    let mut status = String::new();
+    let mut shown: Option<(Palette, String)> = None;
+    let mut next = Instant::now();
+
+    loop {
+        for input in read_input() {
+            match input {
+                Input::Key(key) => {
+                    if !held.contains_key(&key) {
+                        platform.handle_input(key, true);
+                    }
+                    held.insert(key, frame + HOLD_FRAMES);
+                }
+                Input::Release(key) => {
+                    if held.remove(&key).is_some() {
+                        platform.handle_input(key, false);
+                    }
+                }
+                Input::NextPalette => status = format!("Palette {}", platform.cycle_palette()),
+                Input::Screenshot => {
+                    let path = platform.capture_name("png");
+                    status = match platform.screenshot(&path) {
+                        Ok(()) => format!("Saved a screenshot to {}", path),
+                        Err(e) => e,
+                    };
+                }
+                Input::Record => {
+                    status = match platform.stop_recording() {
+                        Some(message) => message,
+                        None => {
+                            let path = platform.capture_name("gif");
+                            match platform.start_recording(&path) {
+                                Ok(()) => format!("Recording to {}, Ctrl-R stops", path),
+                                Err(e) => e,
+                            }
+                        }
+                    };
+                }
+                Input::Quit => return Ok(()),
+            }
+        }
+        let released: Vec<Key> = held.iter().filter(|(_, until)| **until <= frame).map(|(key, _)| *key).collect();
+        for key in released {
+            held.remove(&key);
+            platform.handle_input(key, false);
+        }
+
+        match &mut dap {
+            Some(dap) => {
+                dap.update(platform);
+            }
+            None => platform.c8_cycle(),
+        }
+        frame += 1;
+
+        //Drawn again when the picture, the palette or the status line changed
+        let palette = platform.palette();
+        let changed = shown.as_ref() != Some(&(palette, status.clone()));
+        if platform.take_screen_dirty() || changed {
+            let mut out = String::from("\x1b[H");
+            let resolution = platform.resolution();
+            if braille {
+                draw_braille(&mut out, platform.framebuffer(), resolution, &palette);
+            } else {
+                draw_half_blocks(&mut out, platform.framebuffer(), resolution, &palette);
+            }
+            let _ = write!(out, "{}  Esc quits, Ctrl-P palette, Ctrl-S screenshot, Ctrl-R record  {}\x1b[K", title, status);
+            let mut stdout = io::stdout().lock();
+            let _ = stdout.write_all(out.as_bytes());
+            let _ = stdout.flush();
+            shown = Some((palette, status.clone()));
+        }
+
+        next += FRAME;
+        let now = Instant::now();
+        if next > now {
+            std::thread::sleep(next - now);
+        } else {
+            next = now;
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+use crate::chip8::Chip8;
+use crate::disasm;
+use crate::symbols::Symbols;
+
+pub const DEFAULT_FORMAT: &str = "cycle,pc,opcode,mnemonic,v,i,sp,dt,st";
+//Lines shown before and after the first difference
+pub const DEFAULT_CONTEXT: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Column {
+    Cycle,
+    Pc,
+    Opcode,
+    Mnemonic,
+    Label,
+    V(usize),
+    I,
+    Sp,
+    Dt,
+    St,
+}
+
+impl Column {
+    fn name(&self) -> String {
+        match self {
+            Column::Cycle => "cycle".to_string(),
+            Column::Pc => "pc".to_string(),
+            Column::Opcode => "opcode".to_string(),
+            Column::Mnemonic => "mnemonic".to_string(),
+            Column::Label => "label".to_string(),
+            Column::V(n) => format!("v{:x}", n),
+            Column::I => "i".to_string(),
+            Column::Sp => "sp".to_string(),
+            Column::Dt => "dt".to_string(),
+            Column::St => "st".to_string(),
+        }
+    }
+}
+
+//A register like v3 or a range of them like v0-v3
+fn register_range(name: &str) -> Option<(usize, usize)> {
+    let register = |name: &str| {
+        let digit = name.strip_prefix('v').filter(|digit| digit.len() == 1)?;
+        usize::from_str_radix(digit, 16).ok()
+    };
+    match name.split_once('-') {
+        Some((first, last)) => Some((register(first)?, register(last)?)).filter(|(first, last)| first <= last),
+        None => register(name).map(|n| (n, n)),
+    }
+}
+
+//Columns separated by commas, v stands for v0 to vf
+fn parse_format(format: &str) -> Result<Vec<Column>, String> {
+    let mut columns = Vec::new();
+    for name in format.split(',').map(|n| n.trim().to_lowercase()) {
+        match name.as_str() {
+            "cycle" => columns.push(Column::Cycle),
+            "pc" => columns.push(Column::Pc),
+            "opcode" | "op" => columns.push(Column::Opcode),
+            "mnemonic" => columns.push(Column::Mnemonic),
+            "label" => columns.push(Column::Label),
+            "v" => columns.extend((0..16).map(Column::V)),
+            "i" => columns.push(Column::I),
+            "sp" => columns.push(Column::Sp),
+            "dt" => columns.push(Column::Dt),
+            "st" => columns.push(Column::St),
+            _ => {
+                let (first, last) = register_range(&name).ok_or(format!("Unknown trace column {}", name))?;
+                columns.extend((first..=last).map(Column::V));
+            }
+        }
+    }
+    Ok(columns)
+}
+
+//Writes one tab separated line per executed instruction with the state before it executes.
+//With a ring size only the last instructions are kept, they are written on a fault or at the end.
+pub struct Tracer {
+    columns: Vec<Column>,
+    out: BufWriter<File>,
+    ring: Option<(usize, VecDeque<String>)>,
+}
+
+impl Tracer {
+    pub fn trace(&mut self, cycles: u64, chip: &Chip8, symbols: &Symbols) {
+        let opcode = disasm::fetch(&chip.mem, chip.pc as usize);
+        let fields: Vec<String> = self.columns.iter().map(|column| match column {
+            Column::Cycle => cycles.to_string(),
+            Column::Pc => format!("{:04x}", chip.pc),
+            Column::Opcode => format!("{:04x}", opcode),
+            Column::Mnemonic => disasm::disassemble_symbols(opcode, symbols),
+            Column::Label => symbols.offset_label(chip.pc).unwrap_or_default(),
+            Column::V(n) => format!("{:02x}", chip.reg[*n]),
+            Column::I => format!("{:04x}", chip.i),
+            Column::Sp => format!("{:x}", chip.sp),
+            Column::Dt => format!("{:02x}", chip.delay),
+            Column::St => format!("{:02x}", chip.sound),
+        }).collect();
+        let line = fields.join("\t");
+
+        match &mut self.ring {
+            Some((size, lines)) => {
+                if lines.len() == *size {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            }
+            None => {
+                let _ = writeln!(self.out, "{}", line);
+            }
+        }
+    }
+
+    //Writes out what the ring buffer holds
+    pub fn fault(&mut self, message: &str) {
+        self.write_ring();
+        let _ = writeln!(self.out, "# {}", message);
+        let _ = self.out.flush();
+    }
+
+    fn write_ring(&mut self) {
+        if let Some((_, lines)) = &mut self.ring {
+            for line in lines.drain(..) {
+                let _ = writeln!(self.out, "{}", line);
+            }
+        }
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        self.write_ring();
+        let _ = self.out.flush();
+    }
+}
+
+pub fn new_tracer(path: &str, format: &str, ring: Option<usize>) -> Result<Tracer, String> {
+    let columns = parse_format(format)?;
+    let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut out = BufWriter::new(file);
+    let names: Vec<String> = columns.iter().map(Column::name).collect();
+    let _ = writeln!(out, "#{}", names.join("\t"));
+    Ok(Tracer { columns, out, ring: ring.map(|size| (size.max(1), VecDeque::new())) })
+}
+
+//Instruction lines of a trace and the column names of its header, comments are skipped
+fn read_trace(path: &str) -> Result<(Vec<String>, Vec<String>), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let header = text.lines().next()
+        .and_then(|line| line.strip_prefix('#'))
+        .filter(|line| line.contains('\t'))
+        .map(|line| line.split('\t').map(|name| name.to_string()).collect())
+        .unwrap_or_default();
+    let lines = text.lines().filter(|line| !line.starts_with('#')).map(|line| line.trim_end().to_string()).collect();
+    Ok((lines, header))
+}
+
+//Compares two traces and reports the first instruction where they differ; returns whether they are the same
+pub fn diff(a_path: &str, b_path: &str, context: usize) -> Result<bool, String> {
+    let (a, header) = read_trace(a_path)?;
+    let (b, _) = read_trace(b_path)?;
+
+    let first = match (0..a.len().max(b.len())).find(|&n| a.get(n) != b.get(n)) {
+        Some(n) => n,
+        None => {
+            println!("The traces are the same for {} instructions", a.len());
+            return Ok(true);
+        }
+    };
+
+    println!("First difference at instruction {}:", first + 1);
+    for line in &a[first.saturating_sub(context)..first] {
+        println!("  {}", line);
+    }
+    match (a.get(first), b.get(first)) {
+        (Some(x), Some(y)) => {
+            println!("- {}", x);
+            println!("+ {}", y);
+            //Name the columns that differ when the lines are in the tab separated format
+            let x_fields: Vec<&str> = x.split('\t').collect();
+            let y_fields: Vec<&str> = y.split('\t').collect();
+            if x_fields.len() > 1 && x_fields.len() == y_fields.len() {
+                for (n, (p, q)) in x_fields.iter().zip(&y_fields).enumerate() {
+                    if p != q {
+                        let name = header.get(n).cloned().unwrap_or_else(|| format!("column {}", n + 1));
+                        println!("  {}: {} in {}, {} in {}", name, p, a_path, q, b_path);
+                    }
+                }
+            }
+        }
+        (Some(_), None) => println!("{} ends after {} instructions", b_path, b.len()),
+        _ => println!("{} ends after {} instructions", a_path, a.len()),
+    }
+    for (name, lines) in [(a_path, &a), (b_path, &b)] {
+        let after: Vec<&String> = lines.iter().skip(first + 1).take(context).collect();
+        if !after.is_empty() {
+            println!("then in {}:", name);
+            for line in after {
+                println!("  {}", line);
+            }
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_ranges() {
+        let columns = parse_format("cycle,pc,v0-vf").unwrap();
+        assert_eq!(columns.len(), 18);
+        assert_eq!(parse_format("v3-v5,va").unwrap(), vec![Column::V(3), Column::V(4), Column::V(5), Column::V(10)]);
+        assert!(parse_format("v5-v3").is_err());
+        assert!(parse_format("v0-vg").is_err());
+    }
+}
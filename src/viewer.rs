@@ -0,0 +1,256 @@
+use graphics::{rectangle, Context, Graphics};
+use piston::Key;
+
+use crate::access;
+use crate::chip8::Chip8;
+use crate::disasm;
+use crate::font;
+use crate::platform::Platform;
+
+const BACKGROUND: [f32; 4] = [0.05, 0.05, 0.08, 0.95];
+const TEXT: [f32; 4] = [0.85, 0.85, 0.85, 1.0];
+const DIM: [f32; 4] = [0.5, 0.5, 0.55, 1.0];
+const PIXEL: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+const SPRITE: [f32; 4] = [0.15, 0.15, 0.2, 1.0];
+//the background of a sprite
+const AT_I: [f32; 4] = [1.0, 0.55, 0.1, 1.0];
+//bytes the next DXYN draws, or the sprite height at I
+const CURSOR: [f32; 4] = [0.2, 0.35, 0.6, 1.0];
+const PX: f64 = 2.0;
+const MARGIN: f64 = 20.0;
+//Size of a sprite pixel and the gap between sprites
+const SPRITE_PX: f64 = 4.0;
+const GAP: f64 = 8.0;
+const BYTES_PER_ROW: usize = 8;
+const MEM_SIZE: usize = 4096;
+
+//Where the parts of the viewer go in a window of a given size
+struct Layout {
+    top: f64,
+    rows: usize,
+    //rows of the hex editor
+    hex_left: f64,
+    sprite_columns: usize,
+    sprite_rows: usize,
+}
+
+fn layout(window_size: [f64; 2], height: u8) -> Layout {
+    let row_height = font::CELL_HEIGHT * PX;
+    let top = MARGIN + 2.0 * row_height;
+    let bottom = window_size[1] - MARGIN - 2.0 * row_height;
+    let hex_width = (7 + 3 * BYTES_PER_ROW) as f64 * font::CELL_WIDTH * PX;
+    let hex_left = (window_size[0] - MARGIN - hex_width).max(MARGIN);
+    let sprite_width = 8.0 * SPRITE_PX + GAP;
+    let sprite_height = height as f64 * SPRITE_PX + GAP;
+    Layout {
+        top,
+        rows: ((bottom - top) / row_height).max(1.0) as usize,
+        hex_left,
+        sprite_columns: ((hex_left - 2.0 * MARGIN) / sprite_width).max(1.0) as usize,
+        sprite_rows: ((bottom - top) / sprite_height).max(1.0) as usize,
+    }
+}
+
+//Memory as sprites of 8 pixels width and a chosen height next to a hex editor. Edits go straight into the
+//running machine; the bytes at I are highlighted.
+pub struct Viewer {
+    start: usize,
+    //first address of the hex editor, a multiple of BYTES_PER_ROW
+    sprite_start: usize,
+    //first address of the sprites, it follows the hex editor unless the sprite at I was asked for
+    cursor: usize,
+    //byte selected in the hex editor
+    high_nibble: Option<u8>,
+    //first hex digit typed for the byte at the cursor
+    height: u8,
+}
+
+impl Viewer {
+    //Bytes at I that the next instruction draws if it is a DXYN, otherwise a sprite of the chosen height
+    fn region_at_i(&self, chip: &Chip8) -> (usize, usize) {
+        match access::next_access(chip).mem_reads {
+            Some((start, len)) if disasm::fetch(&chip.mem, chip.pc as usize) >> 12 == 0xd => (start as usize, len as usize),
+            _ => (chip.i as usize, self.height as usize),
+        }
+    }
+
+    pub fn draw<G: Graphics>(&self, chip: &Chip8, window_size: [f64; 2], c: &Context, gl: &mut G) {
+        let row_height = font::CELL_HEIGHT * PX;
+        let layout = layout(window_size, self.height);
+        let (i_start, i_len) = self.region_at_i(chip);
+        let at_i = |addr: usize| addr >= i_start && addr < i_start + i_len;
+
+        rectangle(BACKGROUND, [0.0, 0.0, window_size[0], window_size[1]], c.transform, gl);
+        let title = format!("MEMORY {:#05X}  HEIGHT {}  I {:#05X}", self.cursor, self.height, chip.i);
+        font::draw_text(&title, MARGIN, MARGIN, PX, TEXT, c.transform, gl);
+        let help = "Arrows move, 0-F edit, +/- height, I goes to I, click a pixel to toggle it, F3 closes";
+        font::draw_text(help, MARGIN, window_size[1] - MARGIN - row_height, PX, DIM, c.transform, gl);
+
+        //Sprites, column by column
+        for n in 0..layout.sprite_columns * layout.sprite_rows {
+            let addr = self.sprite_start + n * self.height as usize;
+            if addr >= MEM_SIZE {
+                break;
+            }
+            let x = MARGIN + (n / layout.sprite_rows) as f64 * (8.0 * SPRITE_PX + GAP);
+            let y = layout.top + (n % layout.sprite_rows) as f64 * (self.height as f64 * SPRITE_PX + GAP);
+            rectangle(SPRITE, [x, y, 8.0 * SPRITE_PX, self.height as f64 * SPRITE_PX], c.transform, gl);
+            for row in 0..self.height as usize {
+                let byte_addr = addr + row;
+                if byte_addr >= MEM_SIZE {
+                    break;
+                }
+                let color = if at_i(byte_addr) { AT_I } else { PIXEL };
+                for bit in 0..8 {
+                    if chip.mem[byte_addr] & (0x80 >> bit) != 0 {
+                        let rect = [x + bit as f64 * SPRITE_PX, y + row as f64 * SPRITE_PX, SPRITE_PX, SPRITE_PX];
+                        rectangle(color, rect, c.transform, gl);
+                    }
+                }
+            }
+        }
+
+        //Hex editor
+        let cell = font::CELL_WIDTH * PX;
+        for row in 0..layout.rows {
+            let addr = self.start + row * BYTES_PER_ROW;
+            if addr >= MEM_SIZE {
+                break;
+            }
+            let y = layout.top + row as f64 * row_height;
+            font::draw_text(&format!("{:#05X}", addr), layout.hex_left, y, PX, DIM, c.transform, gl);
+            for column in 0..BYTES_PER_ROW {
+                let byte_addr = addr + column;
+                let x = layout.hex_left + (7 + 3 * column) as f64 * cell;
+                if byte_addr == self.cursor {
+                    rectangle(CURSOR, [x - PX, y - PX, 2.0 * cell + PX, row_height], c.transform, gl);
+                }
+                let text = match self.high_nibble {
+                    Some(nibble) if byte_addr == self.cursor => format!("{:X}_", nibble),
+                    _ => format!("{:02X}", chip.mem[byte_addr]),
+                };
+                let color = if at_i(byte_addr) { AT_I } else { TEXT };
+                font::draw_text(&text, x, y, PX, color, c.transform, gl);
+            }
+        }
+    }
+
+    fn move_cursor(&mut self, delta: i64, window_size: [f64; 2]) {
+        self.cursor = (self.cursor as i64 + delta).clamp(0, MEM_SIZE as i64 - 1) as usize;
+        self.high_nibble = None;
+        //Scroll the hex editor so that the cursor stays in view
+        let rows = layout(window_size, self.height).rows;
+        let row = self.cursor / BYTES_PER_ROW;
+        let first = self.start / BYTES_PER_ROW;
+        if row < first {
+            self.start = row * BYTES_PER_ROW;
+            self.sprite_start = self.start;
+        } else if row >= first + rows {
+            self.start = (row + 1 - rows) * BYTES_PER_ROW;
+            self.sprite_start = self.start;
+        }
+    }
+
+    pub fn press(&mut self, key: Key, platform: &mut Platform, window_size: [f64; 2]) {
+        let page = (layout(window_size, self.height).rows * BYTES_PER_ROW) as i64;
+        match key {
+            Key::Left => self.move_cursor(-1, window_size),
+            Key::Right => self.move_cursor(1, window_size),
+            Key::Up => self.move_cursor(-(BYTES_PER_ROW as i64), window_size),
+            Key::Down => self.move_cursor(BYTES_PER_ROW as i64, window_size),
+            Key::PageUp => self.move_cursor(-page, window_size),
+            Key::PageDown => self.move_cursor(page, window_size),
+            Key::Plus | Key::Equals | Key::NumPadPlus => self.height = (self.height + 1).min(15),
+            Key::Minus | Key::NumPadMinus => self.height = (self.height - 1).max(1),
+            Key::I => {
+                //The sprite at I becomes the first one shown
+                let i = platform.chip().i as usize;
+                self.move_cursor(i as i64 - self.cursor as i64, window_size);
+                self.sprite_start = i.min(MEM_SIZE - 1);
+            }
+            Key::Backspace => self.high_nibble = None,
+            _ => {
+                if let Some(digit) = hex_digit(key) {
+                    match self.high_nibble.take() {
+                        Some(high) => {
+                            platform.chip_mut().mem[self.cursor] = high << 4 | digit;
+                            self.move_cursor(1, window_size);
+                        }
+                        None => self.high_nibble = Some(digit),
+                    }
+                }
+            }
+        }
+    }
+
+    //A click on a sprite pixel toggles it, one on a byte of the hex editor selects it
+    pub fn click(&mut self, position: [f64; 2], platform: &mut Platform, window_size: [f64; 2]) {
+        let layout = layout(window_size, self.height);
+        let [x, y] = position;
+        if y < layout.top {
+            return;
+        }
+        let cell = font::CELL_WIDTH * PX;
+        if x >= layout.hex_left {
+            let row = ((y - layout.top) / (font::CELL_HEIGHT * PX)) as usize;
+            let column = ((x - layout.hex_left) / cell - 7.0) / 3.0;
+            if column >= 0.0 && (column as usize) < BYTES_PER_ROW && row < layout.rows {
+                let addr = self.start + row * BYTES_PER_ROW + column as usize;
+                self.move_cursor(addr as i64 - self.cursor as i64, window_size);
+            }
+            return;
+        }
+
+        let sprite_width = 8.0 * SPRITE_PX + GAP;
+        let sprite_height = self.height as f64 * SPRITE_PX + GAP;
+        let (column, row) = (((x - MARGIN) / sprite_width).floor(), ((y - layout.top) / sprite_height).floor());
+        if column < 0.0 || column as usize >= layout.sprite_columns || row as usize >= layout.sprite_rows {
+            return;
+        }
+        let bit = ((x - MARGIN - column * sprite_width) / SPRITE_PX) as usize;
+        let line = ((y - layout.top - row * sprite_height) / SPRITE_PX) as usize;
+        if bit >= 8 || line >= self.height as usize {
+            return;
+        }
+        let n = column as usize * layout.sprite_rows + row as usize;
+        let addr = self.sprite_start + n * self.height as usize + line;
+        if addr < MEM_SIZE {
+            platform.chip_mut().mem[addr] ^= 0x80 >> bit;
+            self.move_cursor(addr as i64 - self.cursor as i64, window_size);
+        }
+    }
+}
+
+fn hex_digit(key: Key) -> Option<u8> {
+    let digit = match key {
+        Key::D0 | Key::NumPad0 => 0x0,
+        Key::D1 | Key::NumPad1 => 0x1,
+        Key::D2 | Key::NumPad2 => 0x2,
+        Key::D3 | Key::NumPad3 => 0x3,
+        Key::D4 | Key::NumPad4 => 0x4,
+        Key::D5 | Key::NumPad5 => 0x5,
+        Key::D6 | Key::NumPad6 => 0x6,
+        Key::D7 | Key::NumPad7 => 0x7,
+        Key::D8 | Key::NumPad8 => 0x8,
+        Key::D9 | Key::NumPad9 => 0x9,
+        Key::A => 0xa,
+        Key::B => 0xb,
+        Key::C => 0xc,
+        Key::D => 0xd,
+        Key::E => 0xe,
+        Key::F => 0xf,
+        _ => return None,
+    };
+    Some(digit)
+}
+
+//Starts at the rom with the height of the font sprites
+pub fn new_viewer() -> Viewer {
+    Viewer {
+        start: 0x200,
+        sprite_start: 0x200,
+        cursor: 0x200,
+        high_nibble: None,
+        height: 5,
+    }
+}